@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tribles::triblearchive::SimpleArchive;
+use tribles::{Bloblike, TribleSet};
+
+// Every accepted blob has to be the canonical archive of its tribles.
+fuzz_target!(|blob: &[u8]| {
+    if let Ok(archive) = SimpleArchive::from_blob(blob.to_vec().into()) {
+        let set = TribleSet::from(&archive);
+        assert_eq!(&SimpleArchive::from(&set).into_blob()[..], blob);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tribles::trible::{Trible, A_END, A_START, E_END, E_START};
+use tribles::triblearchive::SimpleArchive;
+use tribles::{Bloblike, TribleSet};
+
+// Every archived set has to be accepted and decode to the same set.
+fuzz_target!(|tribles: Vec<Trible>| {
+    let set: TribleSet = tribles
+        .into_iter()
+        .filter(|t| t.data[E_START..=E_END] != [0; 16] && t.data[A_START..=A_END] != [0; 16])
+        .collect();
+    let blob = SimpleArchive::from(&set).into_blob();
+    let archive = SimpleArchive::from_blob(blob).expect("archive of a set is canonical");
+    assert!(TribleSet::from(&archive) == set);
+});
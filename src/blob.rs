@@ -1,4 +1,6 @@
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{self, Debug};
 use digest::{consts::U32, Digest};
 
 use crate::{types::Hash, Handle};
@@ -32,15 +34,52 @@ impl<'a> Bloblike for Bytes {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct BlobParseError {
-    msg: String,
+/// Why a [Blob] couldn't be converted into a [Bloblike] type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BlobParseError {
+    /// The blob has a length that is invalid for the type.
+    BadLength(usize),
+    /// The blob isn't valid UTF-8.
+    BadUtf8,
+    /// An archived trible has a NULL entity or attribute id.
+    NullId,
+    /// An archived trible appears more than once.
+    RedundantTrible,
+    /// The archived tribles are not in ascending order.
+    UnsortedTribles,
+    /// The blob couldn't be deserialized.
+    Deserialize,
+    /// The blob has bytes left after deserializing it.
+    TrailingBytes,
+    /// The blob is not the canonical encoding of its content.
+    NonCanonical,
+    /// The blob is malformed in some other way,
+    /// e.g. for types defined outside of this crate.
+    Malformed(Cow<'static, str>),
 }
 
 impl BlobParseError {
+    #[deprecated(note = "use BlobParseError::Malformed or a more specific variant")]
     pub fn new(msg: &str) -> Self {
-        BlobParseError {
-            msg: msg.to_owned(),
+        Self::Malformed(Cow::Owned(msg.to_owned()))
+    }
+}
+
+impl fmt::Display for BlobParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadLength(len) => write!(f, "invalid blob length {}", len),
+            Self::BadUtf8 => write!(f, "blob is not valid utf-8"),
+            Self::NullId => write!(f, "archived trible contains a NULL id"),
+            Self::RedundantTrible => write!(f, "archived trible is redundant"),
+            Self::UnsortedTribles => write!(f, "archived tribles are not sorted"),
+            Self::Deserialize => write!(f, "failed to deserialize blob"),
+            Self::TrailingBytes => write!(f, "blob has trailing bytes"),
+            Self::NonCanonical => write!(f, "blob is not canonically encoded"),
+            Self::Malformed(msg) => write!(f, "malformed blob: {}", msg),
         }
     }
 }
+
+impl Error for BlobParseError {}
@@ -0,0 +1,242 @@
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::io;
+
+use anybytes::Bytes;
+use digest::{typenum::U32, Digest};
+use quick_cache::{sync::Cache, Weighter};
+
+use crate::remote::filestore::{FileRepo, PullErr};
+use crate::remote::repo::{Forget, Pull, Push};
+use crate::types::Hash;
+
+/// Used to estimate the number of blobs that fit into the memory tier.
+const EXPECTED_BLOB_LEN: u64 = 4096;
+
+#[derive(Clone)]
+struct BlobWeighter;
+
+impl<H> Weighter<Hash<H>, Bytes> for BlobWeighter {
+    fn weight(&self, _: &Hash<H>, blob: &Bytes) -> u32 {
+        // Empty blobs still get a weight, as weightless ones are never evicted.
+        blob.len().max(1).try_into().unwrap_or(u32::MAX)
+    }
+}
+
+#[derive(Debug)]
+pub enum BlobCacheErr<PullErr> {
+    Pull(PullErr),
+    Disk(io::Error),
+    /// The repo returned content that doesn't match the hash.
+    Corrupt,
+}
+
+impl<PullErr> fmt::Display for BlobCacheErr<PullErr> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull(_) => write!(f, "failed to pull blob"),
+            Self::Disk(e) => write!(f, "failed to access disk cache: {}", e),
+            Self::Corrupt => write!(f, "pulled blob does not match its hash"),
+        }
+    }
+}
+
+impl<PullErr> Error for BlobCacheErr<PullErr>
+where
+    PullErr: Debug + Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Pull(e) => Some(e),
+            Self::Disk(e) => Some(e),
+            Self::Corrupt => None,
+        }
+    }
+}
+
+/// Caches the blobs pulled from a repo in a size bounded memory tier
+/// and, optionally, a [FileRepo] on the local disk.
+///
+/// Concurrent pulls of the same blob wait for a single fetch,
+/// so dereferencing many handles to the same blob only hits the repo once.
+/// Blobs from the repo are checked against their hash before they are cached.
+pub struct BlobCache<H, R> {
+    repo: R,
+    memory: Cache<Hash<H>, Bytes, BlobWeighter>,
+    disk: Option<FileRepo<H>>,
+}
+
+impl<H, R> BlobCache<H, R>
+where
+    H: Digest<OutputSize = U32>,
+    R: Pull<H>,
+{
+    /// Wraps `repo`, keeping at most `capacity` bytes of blobs in memory.
+    pub fn new(repo: R, capacity: u64) -> Self {
+        let estimated_blobs = (capacity / EXPECTED_BLOB_LEN).max(1);
+        BlobCache {
+            repo,
+            memory: Cache::with_weighter(estimated_blobs as usize, capacity, BlobWeighter),
+            disk: None,
+        }
+    }
+
+    /// Also stores the blobs pulled from the repo in `disk`,
+    /// where they survive evictions and restarts.
+    ///
+    /// Unlike the memory tier the disk tier isn't size bounded, it keeps
+    /// every blob until it is removed, e.g. by [Forget::forget] or by
+    /// deleting its directory while no cache uses it.
+    pub fn with_disk(mut self, disk: FileRepo<H>) -> Self {
+        self.disk = Some(disk);
+        self
+    }
+
+    /// The repo that misses are pulled from.
+    pub fn repo(&self) -> &R {
+        &self.repo
+    }
+
+    async fn fetch(&self, hash: Hash<H>) -> Result<Bytes, BlobCacheErr<R::Err>> {
+        if let Some(disk) = &self.disk {
+            match disk.pull(hash).await {
+                Ok(blob) => return Ok(blob),
+                Err(PullErr::IO(e)) if e.kind() == io::ErrorKind::NotFound => {}
+                // The damaged copy is replaced with the one from the repo.
+                Err(PullErr::Corrupt) => disk.forget(hash).await.map_err(BlobCacheErr::Disk)?,
+                Err(PullErr::IO(e)) => return Err(BlobCacheErr::Disk(e)),
+            }
+        }
+        let blob = self.repo.pull(hash).await.map_err(BlobCacheErr::Pull)?;
+        if Hash::<H>::digest(&blob) != hash {
+            return Err(BlobCacheErr::Corrupt);
+        }
+        if let Some(disk) = &self.disk {
+            disk.push(blob.clone()).await.map_err(BlobCacheErr::Disk)?;
+        }
+        Ok(blob)
+    }
+}
+
+impl<H, R> Pull<H> for BlobCache<H, R>
+where
+    H: Digest<OutputSize = U32>,
+    R: Pull<H>,
+{
+    type Err = BlobCacheErr<R::Err>;
+
+    async fn pull(&self, hash: Hash<H>) -> Result<Bytes, Self::Err> {
+        match self.memory.get_value_or_guard_async(&hash).await {
+            Ok(blob) => Ok(blob),
+            Err(guard) => {
+                // Other pulls of this hash wait until the guard is filled or dropped.
+                let blob = self.fetch(hash).await?;
+                let _ = guard.insert(blob.clone());
+                Ok(blob)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::ufoid;
+    use crate::remote::repo::NotFoundErr;
+    use crate::types::{hash::Blake3, ZCString};
+    use crate::BlobSet;
+    use futures::executor::block_on;
+    use futures::future;
+    use std::cell::Cell;
+    use std::iter::FromIterator;
+    use std::task::Poll;
+
+    struct CountingRepo {
+        blobs: BlobSet<Blake3>,
+        pulls: Cell<usize>,
+    }
+
+    impl Pull<Blake3> for CountingRepo {
+        type Err = NotFoundErr;
+
+        async fn pull(&self, hash: Hash<Blake3>) -> Result<Bytes, Self::Err> {
+            self.pulls.set(self.pulls.get() + 1);
+            // Yield once, so that concurrent pulls overlap.
+            let mut yielded = false;
+            future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            })
+            .await;
+            self.blobs.pull(hash).await
+        }
+    }
+
+    fn counting_repo() -> (CountingRepo, Hash<Blake3>) {
+        let mut blobs = BlobSet::new();
+        let handle = blobs.put(ZCString::from("cached".to_string()));
+        let repo = CountingRepo {
+            blobs,
+            pulls: Cell::new(0),
+        };
+        (repo, handle.hash)
+    }
+
+    #[test]
+    fn pulls_once() {
+        let (repo, hash) = counting_repo();
+        let cache = BlobCache::new(repo, 1024 * 1024);
+
+        let (a, b) = block_on(future::join(cache.pull(hash), cache.pull(hash)));
+        assert_eq!(&a.unwrap()[..], b"cached");
+        assert_eq!(&b.unwrap()[..], b"cached");
+        assert_eq!(
+            block_on(cache.pull(hash)).unwrap(),
+            block_on(cache.pull(hash)).unwrap()
+        );
+        assert_eq!(cache.repo().pulls.get(), 1);
+    }
+
+    #[test]
+    fn disk_tier() {
+        let dir = std::env::temp_dir().join(format!("tribles-blobcache-{}", hex::encode(ufoid())));
+        let (repo, hash) = counting_repo();
+
+        let cache = BlobCache::new(repo, 1024 * 1024).with_disk(FileRepo::open(&dir).unwrap());
+        block_on(cache.pull(hash)).unwrap();
+        assert_eq!(cache.repo().pulls.get(), 1);
+
+        // A fresh memory tier is filled from the disk.
+        let cache =
+            BlobCache::new(cache.repo, 1024 * 1024).with_disk(FileRepo::open(&dir).unwrap());
+        assert_eq!(&block_on(cache.pull(hash)).unwrap()[..], b"cached");
+        assert_eq!(cache.repo().pulls.get(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupt_blobs() {
+        let dir = std::env::temp_dir().join(format!("tribles-blobcache-{}", hex::encode(ufoid())));
+        let (repo, hash) = counting_repo();
+        let repo = CountingRepo {
+            blobs: BlobSet::from_iter([(hash, Bytes::from(b"forged".to_vec()))]),
+            ..repo
+        };
+
+        let cache = BlobCache::new(repo, 1024 * 1024).with_disk(FileRepo::open(&dir).unwrap());
+        assert!(matches!(block_on(cache.pull(hash)), Err(BlobCacheErr::Corrupt)));
+        // Neither tier kept the forged content.
+        assert!(matches!(block_on(cache.pull(hash)), Err(BlobCacheErr::Corrupt)));
+        assert_eq!(cache.repo().pulls.get(), 2);
+        let disk: FileRepo<Blake3> = FileRepo::open(&dir).unwrap();
+        assert!(block_on(disk.pull(hash)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod bitset;
 pub mod blob;
+pub mod blobcache;
 pub mod blobset;
 pub mod bytetable;
 pub mod column;
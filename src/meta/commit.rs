@@ -1,20 +1,29 @@
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+
 use ed25519::Signature;
 use ed25519_dalek::SigningKey;
+use futures::{stream, Stream, StreamExt};
 use itertools::Itertools;
 
 use ed25519::signature::{Signer, Verifier};
 
 use crate::{
-    namespace::NS,
+    id::{ufoid, ID_LEN},
+    namespace::{hex_literal, NS},
     query::find,
+    remote::repo::Pull,
+    trible::{A_END, A_START, E_END, E_START, TRIBLE_LEN},
     triblearchive::SimpleArchive,
     types::{
         ed25519 as ed,
         ed25519::{RComponent, SComponent},
         hash::Blake3,
-        ShortString,
+        Hash, ShortString,
     },
-    Handle, Id, TribleSet,
+    BlobParseError, Bloblike, Handle, Id, TribleSet, ValueParseError,
 };
 
 NS! {
@@ -25,42 +34,162 @@ NS! {
         "9DF34F84959928F93A3C40AEB6E9E499" as ed25519_signature_r: ed::RComponent;
         "1ACE03BF70242B289FDF00E4327C3BC6" as ed25519_signature_s: ed::SComponent;
         "B57D92D4630F8F1B697DAF49CDFA3757" as ed25519_pubkey: ed::VerifyingKey;
+        "76778BFE32600DEBFFDDB8B6C2D0B132" as parent: Handle<Blake3, SimpleArchive>;
+        "55E6A4948C42836ED6FA8B5B74CC0010" as signature_version: Id;
+    }
+}
+
+/// The [commit_ns::signature_version] of signatures over the commit metadata,
+/// see [metadata_message].
+/// Commits without a version were signed over the payload hash only,
+/// so [verify] rejects them if they carry further metadata.
+pub const METADATA_SIGNATURE_V1: Id = hex_literal::hex!("BD8F542FED4E05B498C104635FD9C320");
+
+// Keys whose signatures are accepted by [verify_history].
+NS! {
+    pub namespace trust_ns {
+        "1B6B047515ABD2F1B9AFF85CAEB07922" as trusted_key: ed::VerifyingKey;
     }
 }
 
-pub struct ValidationError {
-    #[allow(unused)]
-    msg: String,
+/// Why a commit failed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationError {
+    /// The commit has more than one signature.
+    AmbiguousSignature,
+    /// The commit has no signature.
+    MissingSignature,
+    /// The signature doesn't match the signed data.
+    BadSignature,
+    /// The commit has more than one signature version.
+    AmbiguousSignatureVersion,
+    /// The commit was signed with an unknown version of the signature scheme.
+    UnknownSignatureVersion(Id),
+    /// The commit has more than one payload.
+    AmbiguousPayload,
+    /// The commit has no payload.
+    MissingPayload,
+    /// A trible of the commit holds a malformed value.
+    BadValue(ValueParseError),
+    /// The commit was signed over its payload only, but carries further
+    /// metadata that no signature covers.
+    UnsignedMetadata,
+    /// The commit holds tribles of an entity other than the commit itself,
+    /// which no signature covers.
+    ForeignEntity(Id),
+    /// The commit is malformed in some other way.
+    Malformed(Cow<'static, str>),
 }
 
 impl ValidationError {
+    #[deprecated(note = "use ValidationError::Malformed or a more specific variant")]
     pub fn new(msg: &str) -> ValidationError {
-        ValidationError {
-            msg: msg.to_owned(),
+        Self::Malformed(Cow::Owned(msg.to_owned()))
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AmbiguousSignature => write!(f, "ambiguous signature in commit"),
+            Self::MissingSignature => write!(f, "no signature in commit"),
+            Self::BadSignature => write!(f, "couldn't validate signature"),
+            Self::AmbiguousSignatureVersion => write!(f, "ambiguous signature version in commit"),
+            Self::UnknownSignatureVersion(version) => {
+                write!(f, "unknown signature version {}", hex::encode(version))
+            }
+            Self::AmbiguousPayload => write!(f, "ambiguous payload in commit"),
+            Self::MissingPayload => write!(f, "no payload in commit"),
+            Self::BadValue(e) => write!(f, "unexpected bad value in tribles: {}", e),
+            Self::UnsignedMetadata => write!(f, "unsigned metadata in commit"),
+            Self::ForeignEntity(id) => {
+                write!(f, "tribles of foreign entity {} in commit", hex::encode(id))
+            }
+            Self::Malformed(msg) => write!(f, "malformed commit: {}", msg),
+        }
+    }
+}
+
+impl Error for ValidationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::BadValue(e) => Some(e),
+            _ => None,
         }
     }
 }
 
+impl From<ValueParseError> for ValidationError {
+    fn from(err: ValueParseError) -> Self {
+        Self::BadValue(err)
+    }
+}
+
+/// Returns the canonical serialization of the commit metadata that is signed,
+/// i.e. the [SimpleArchive] of the tribles of the commit entity,
+/// without the ones holding the signature itself.
+pub fn metadata_message(tribles: &TribleSet, commit_id: Id) -> Vec<u8> {
+    let archive = SimpleArchive::from(tribles).into_blob();
+    archive
+        .chunks_exact(TRIBLE_LEN)
+        .filter(|t| {
+            let a = &t[A_START..=A_END];
+            t[E_START..=E_END] == commit_id
+                && a != commit_ns::ids::ed25519_signature_r
+                && a != commit_ns::ids::ed25519_signature_s
+        })
+        .flatten()
+        .copied()
+        .collect()
+}
+
+/// Signs a commit of the given payload without further metadata.
 pub fn sign(
     signing_key: SigningKey,
     handle: Handle<Blake3, SimpleArchive>,
     commit_id: Id,
 ) -> Result<TribleSet, ValidationError> {
-    let hash = handle.hash.bytes;
-    let signature = signing_key.sign(&hash);
+    sign_metadata(
+        signing_key,
+        commit_id,
+        commit_ns::entity!(commit_id, { tribles: handle }),
+    )
+}
+
+/// Signs the metadata of a commit, e.g. its payload, parents and message,
+/// and returns it together with the signature tribles.
+pub fn sign_metadata(
+    signing_key: SigningKey,
+    commit_id: Id,
+    metadata: TribleSet,
+) -> Result<TribleSet, ValidationError> {
+    let mut tribles = metadata;
+    tribles.union(commit_ns::entity!(commit_id,
+    {
+        ed25519_pubkey: signing_key.verifying_key(),
+        signature_version: METADATA_SIGNATURE_V1,
+    }));
+    let signature = signing_key.sign(&metadata_message(&tribles, commit_id));
     let r = RComponent::from_signature(signature);
     let s = SComponent::from_signature(signature);
-    let tribles = commit_ns::entity!(commit_id,
+    tribles.union(commit_ns::entity!(commit_id,
     {
-        tribles: handle,
-        ed25519_pubkey: signing_key.verifying_key(),
         ed25519_signature_r: r,
         ed25519_signature_s: s,
-    });
+    }));
     Ok(tribles)
 }
 
 pub fn verify(tribles: TribleSet, commit_id: Id) -> Result<(), ValidationError> {
+    if let Some((entity, _)) = tribles
+        .eav
+        .iter_prefix::<ID_LEN>()
+        .find(|(entity, _)| *entity != commit_id)
+    {
+        return Err(ValidationError::ForeignEntity(entity));
+    }
+
     let (payload, verifying_key, r, s) = find!(
         ctx,
         (payload, key, r, s),
@@ -73,13 +202,709 @@ pub fn verify(tribles: TribleSet, commit_id: Id) -> Result<(), ValidationError>
         }])
     )
     .at_most_one()
-    .map_err(|_| ValidationError::new("ambiguous signature in commit"))?
-    .ok_or(ValidationError::new("no signature in commit"))?
-    .map_err(|_| ValidationError::new("unexpected bad value in tribles"))?;
+    .map_err(|_| ValidationError::AmbiguousSignature)?
+    .ok_or(ValidationError::MissingSignature)?
+    .map_err(ValidationError::BadValue)?;
 
-    let hash = payload.hash.bytes;
+    let version = find!(
+        ctx,
+        (version,),
+        commit_ns::pattern!(ctx, tribles, [{(commit_id) @ signature_version: version}])
+    )
+    .at_most_one()
+    .map_err(|_| ValidationError::AmbiguousSignatureVersion)?
+    .transpose()
+    .map_err(ValidationError::BadValue)?;
+
+    let message = match version {
+        None => {
+            if has_unsigned_metadata(&tribles, commit_id) {
+                return Err(ValidationError::UnsignedMetadata);
+            }
+            payload.hash.bytes.to_vec()
+        }
+        Some((METADATA_SIGNATURE_V1,)) => metadata_message(&tribles, commit_id),
+        Some((version,)) => return Err(ValidationError::UnknownSignatureVersion(version)),
+    };
     let signature = Signature::from_components(r.0, s.0);
     verifying_key
-        .verify(&hash, &signature)
-        .map_err(|_| ValidationError::new("couldn't validate signature"))
+        .verify(&message, &signature)
+        .map_err(|_| ValidationError::BadSignature)
+}
+
+/// Checks whether the commit has tribles besides the payload and the signature,
+/// which a signature over the payload hash alone doesn't cover.
+fn has_unsigned_metadata(tribles: &TribleSet, commit_id: Id) -> bool {
+    let archive = SimpleArchive::from(tribles).into_blob();
+    archive.chunks_exact(TRIBLE_LEN).any(|t| {
+        let a = &t[A_START..=A_END];
+        t[E_START..=E_END] == commit_id
+            && a != commit_ns::ids::tribles
+            && a != commit_ns::ids::ed25519_pubkey
+            && a != commit_ns::ids::ed25519_signature_r
+            && a != commit_ns::ids::ed25519_signature_s
+    })
+}
+
+/// Builds the tribles of a commit, which are archived into a [SimpleArchive]
+/// blob whose handle identifies the commit, e.g. as the parent of the next one.
+pub struct CommitBuilder {
+    payload: Handle<Blake3, SimpleArchive>,
+    parents: Vec<Handle<Blake3, SimpleArchive>>,
+    short_message: Option<ShortString>,
+    authored_by: Option<Id>,
+}
+
+impl CommitBuilder {
+    /// Starts a commit of the given payload.
+    pub fn new(payload: Handle<Blake3, SimpleArchive>) -> Self {
+        CommitBuilder {
+            payload,
+            parents: Vec::new(),
+            short_message: None,
+            authored_by: None,
+        }
+    }
+
+    /// Adds a parent commit, merges have more than one.
+    pub fn parent(mut self, parent: Handle<Blake3, SimpleArchive>) -> Self {
+        self.parents.push(parent);
+        self
+    }
+
+    pub fn short_message(mut self, short_message: ShortString) -> Self {
+        self.short_message = Some(short_message);
+        self
+    }
+
+    pub fn authored_by(mut self, author: Id) -> Self {
+        self.authored_by = Some(author);
+        self
+    }
+
+    /// Signs the metadata and returns the tribles of the commit.
+    pub fn sign(self, signing_key: SigningKey) -> Result<TribleSet, ValidationError> {
+        let commit_id = ufoid();
+        let mut metadata = commit_ns::entity!(commit_id, { tribles: self.payload });
+        for parent in self.parents {
+            metadata.union(commit_ns::entity!(commit_id, { parent: parent }));
+        }
+        if let Some(short_message) = self.short_message {
+            metadata.union(commit_ns::entity!(commit_id, { short_message: short_message }));
+        }
+        if let Some(author) = self.authored_by {
+            metadata.union(commit_ns::entity!(commit_id, { authored_by: author }));
+        }
+        sign_metadata(signing_key, commit_id, metadata)
+    }
+}
+
+/// Returns the entity of the commit described by `tribles`,
+/// i.e. the one that holds the payload.
+pub fn commit_entity(tribles: &TribleSet) -> Result<Id, ValidationError> {
+    let (commit_id, _) = find!(
+        ctx,
+        (commit, payload),
+        commit_ns::pattern!(ctx, tribles, [{commit @ tribles: payload}])
+    )
+    .at_most_one()
+    .map_err(|_| ValidationError::AmbiguousPayload)?
+    .ok_or(ValidationError::MissingPayload)?
+    .map_err(ValidationError::BadValue)?;
+    Ok(commit_id)
+}
+
+/// Returns the payload of the commit `commit_id` described by `tribles`.
+pub fn payload(
+    tribles: &TribleSet,
+    commit_id: Id,
+) -> Result<Handle<Blake3, SimpleArchive>, ValidationError> {
+    let (payload,) = find!(
+        ctx,
+        (payload,),
+        commit_ns::pattern!(ctx, tribles, [{(commit_id) @ tribles: payload}])
+    )
+    .at_most_one()
+    .map_err(|_| ValidationError::AmbiguousPayload)?
+    .ok_or(ValidationError::MissingPayload)?
+    .map_err(ValidationError::BadValue)?;
+    Ok(payload)
+}
+
+/// Returns the parents of the commit `commit_id` described by `tribles`.
+///
+/// The parents are returned irrespective of whether a signature covers them,
+/// see [signed_parents].
+pub fn parents(
+    tribles: &TribleSet,
+    commit_id: Id,
+) -> Result<Vec<Handle<Blake3, SimpleArchive>>, ValidationError> {
+    find!(
+        ctx,
+        (parent,),
+        commit_ns::pattern!(ctx, tribles, [{(commit_id) @ parent: parent}])
+    )
+    .map(|r| r.map(|(parent,)| parent).map_err(ValidationError::BadValue))
+    .collect()
+}
+
+/// Returns the parents of the commit `commit_id` described by `tribles`, or none
+/// if it was signed over its payload only, whose signature doesn't cover them.
+pub fn signed_parents(
+    tribles: &TribleSet,
+    commit_id: Id,
+) -> Result<Vec<Handle<Blake3, SimpleArchive>>, ValidationError> {
+    let versioned = find!(
+        ctx,
+        (version,),
+        commit_ns::pattern!(ctx, tribles, [{(commit_id) @ signature_version: version}])
+    )
+    .next()
+    .is_some();
+    if !versioned {
+        return Ok(Vec::new());
+    }
+    parents(tribles, commit_id)
+}
+
+#[derive(Debug)]
+pub enum LoadErr<E> {
+    Pull(E),
+    Parse(BlobParseError),
+    Validation(ValidationError),
+}
+
+/// Pulls the tribles of a commit, or of a payload, from `repo`.
+pub async fn load<R>(
+    repo: &R,
+    handle: Handle<Blake3, SimpleArchive>,
+) -> Result<TribleSet, LoadErr<R::Err>>
+where
+    R: Pull<Blake3>,
+{
+    let blob = repo.pull(handle.hash).await.map_err(LoadErr::Pull)?;
+    let archive = SimpleArchive::from_blob(blob).map_err(LoadErr::Parse)?;
+    Ok(TribleSet::from(&archive))
+}
+
+/// A commit together with its tribles, as yielded by [history].
+pub type LoadedCommit = (Handle<Blake3, SimpleArchive>, TribleSet);
+
+/// Walks the ancestry of `head` breadth first, starting with `head` itself,
+/// and yields every commit once together with its tribles.
+///
+/// Only the [signed_parents] are followed, but the signatures aren't checked,
+/// which is what [verify_history] is for.
+pub fn history<'a, R>(
+    repo: &'a R,
+    head: Handle<Blake3, SimpleArchive>,
+) -> impl Stream<Item = Result<LoadedCommit, LoadErr<R::Err>>> + 'a
+where
+    R: Pull<Blake3>,
+{
+    let mut seen = HashSet::new();
+    seen.insert(head.hash);
+    let queue = VecDeque::from([head]);
+    stream::unfold((queue, seen), move |(mut queue, mut seen)| async move {
+        let commit = queue.pop_front()?;
+        let result = match load(repo, commit).await {
+            Ok(tribles) => {
+                match commit_entity(&tribles).and_then(|id| signed_parents(&tribles, id)) {
+                    Ok(parents) => {
+                        for parent in parents {
+                            if seen.insert(parent.hash) {
+                                queue.push_back(parent);
+                            }
+                        }
+                        Ok((commit, tribles))
+                    }
+                    Err(e) => Err(LoadErr::Validation(e)),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            // The parents are unknown, so the walk can't continue.
+            queue.clear();
+        }
+        Some((result, (queue, seen)))
+    })
+}
+
+/// Returns the nearest common ancestor of `a` and `b`, if they have one.
+///
+/// Both histories are walked alternately, one commit at a time,
+/// and the walk stops at the first commit that was seen on both sides,
+/// so only the commits above the merge base and about as many below it
+/// are loaded.
+pub async fn merge_base<R>(
+    repo: &R,
+    a: Handle<Blake3, SimpleArchive>,
+    b: Handle<Blake3, SimpleArchive>,
+) -> Result<Option<Handle<Blake3, SimpleArchive>>, LoadErr<R::Err>>
+where
+    R: Pull<Blake3>,
+{
+    let mut sides = [
+        (std::pin::pin!(history(repo, a)), HashSet::new()),
+        (std::pin::pin!(history(repo, b)), HashSet::new()),
+    ];
+    let mut exhausted = [false; 2];
+    for side in (0..2).cycle() {
+        if exhausted == [true; 2] {
+            break;
+        }
+        if exhausted[side] {
+            continue;
+        }
+        match sides[side].0.next().await {
+            Some(r) => {
+                let (commit, _) = r?;
+                if sides[1 - side].1.contains(&commit.hash) {
+                    return Ok(Some(commit));
+                }
+                sides[side].1.insert(commit.hash);
+            }
+            None => exhausted[side] = true,
+        }
+    }
+    Ok(None)
+}
+
+/// Merges two versions of a set of tribles that diverged from `base`.
+///
+/// Tribles are kept when both sides have them or when one side added them,
+/// so tribles removed by either side stay removed.
+/// Without a base this is the union of both sides.
+pub fn merge(base: Option<&TribleSet>, ours: &TribleSet, theirs: &TribleSet) -> TribleSet {
+    let mut merged = ours.intersection(theirs);
+    match base {
+        Some(base) => {
+            merged.union(ours.difference(base));
+            merged.union(theirs.difference(base));
+        }
+        None => {
+            merged.union(ours.clone());
+            merged.union(theirs.clone());
+        }
+    }
+    merged
+}
+
+/// Merges the payloads of two commits against the payload of their merge base.
+///
+/// The result still has to be pushed and committed,
+/// with both commits as parents of the merge commit.
+pub async fn merge_commits<R>(
+    repo: &R,
+    ours: Handle<Blake3, SimpleArchive>,
+    theirs: Handle<Blake3, SimpleArchive>,
+) -> Result<TribleSet, LoadErr<R::Err>>
+where
+    R: Pull<Blake3>,
+{
+    let base = match merge_base(repo, ours, theirs).await? {
+        Some(base) => {
+            let commit = load(repo, base).await?;
+            let payload = commit_entity(&commit)
+                .and_then(|id| payload(&commit, id))
+                .map_err(LoadErr::Validation)?;
+            Some(load(repo, payload).await?)
+        }
+        None => None,
+    };
+    let mut payloads = Vec::with_capacity(2);
+    for commit in [ours, theirs] {
+        let commit = load(repo, commit).await?;
+        let payload = commit_entity(&commit)
+            .and_then(|id| payload(&commit, id))
+            .map_err(LoadErr::Validation)?;
+        payloads.push(load(repo, payload).await?);
+    }
+    Ok(merge(base.as_ref(), &payloads[0], &payloads[1]))
+}
+
+/// Why a commit failed [verify_history].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CommitProblem {
+    /// The commit is malformed or its signature is invalid.
+    BadSignature(ValidationError),
+    /// The commit was signed by a key that isn't trusted.
+    UntrustedKey(ed::VerifyingKey),
+    /// The payload blob doesn't match its handle.
+    BadPayload,
+    /// The commit or its payload couldn't be pulled from the repo.
+    Missing(Hash<Blake3>),
+    /// The commit blob isn't a valid archive.
+    Unparseable(BlobParseError),
+}
+
+#[derive(Debug)]
+pub struct VerificationReport {
+    /// The number of commits that passed verification.
+    pub verified: usize,
+    /// The first commit that failed verification, nearest to the head first.
+    pub first_bad: Option<(Handle<Blake3, SimpleArchive>, CommitProblem)>,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.first_bad.is_none()
+    }
+}
+
+/// Verifies every commit reachable from `head`, stopping at the first bad one.
+///
+/// Each commit must carry a valid signature by one of the keys stored
+/// as [trust_ns::trusted_key] in `trusted`, and its payload must be in `repo`
+/// with a matching hash. Commits or payloads that can't be pulled or parsed
+/// are reported like any other problem, so an error only means that
+/// `trusted` is malformed.
+pub async fn verify_history<R>(
+    repo: &R,
+    head: Handle<Blake3, SimpleArchive>,
+    trusted: &TribleSet,
+) -> Result<VerificationReport, LoadErr<R::Err>>
+where
+    R: Pull<Blake3>,
+{
+    let trusted: HashSet<_> = find!(
+        ctx,
+        (key,),
+        trust_ns::pattern!(ctx, trusted, [{trusted_key: key}])
+    )
+    .map(|r| {
+        r.map(|(key,)| key.to_bytes())
+            .map_err(|e| LoadErr::Validation(ValidationError::BadValue(e)))
+    })
+    .collect::<Result<_, _>>()?;
+
+    let mut report = VerificationReport {
+        verified: 0,
+        first_bad: None,
+    };
+    let mut seen = HashSet::from([head.hash]);
+    let mut queue = VecDeque::from([head]);
+    while let Some(commit) = queue.pop_front() {
+        match check_commit(repo, commit, &trusted).await {
+            Ok(parents) => {
+                for parent in parents {
+                    if seen.insert(parent.hash) {
+                        queue.push_back(parent);
+                    }
+                }
+            }
+            Err(problem) => {
+                report.first_bad = Some((commit, problem));
+                break;
+            }
+        }
+        report.verified += 1;
+    }
+    Ok(report)
+}
+
+/// Checks a single commit and returns its parents.
+async fn check_commit<R>(
+    repo: &R,
+    commit: Handle<Blake3, SimpleArchive>,
+    trusted: &HashSet<[u8; 32]>,
+) -> Result<Vec<Handle<Blake3, SimpleArchive>>, CommitProblem>
+where
+    R: Pull<Blake3>,
+{
+    let tribles = match load(repo, commit).await {
+        Ok(tribles) => tribles,
+        Err(LoadErr::Parse(e)) => return Err(CommitProblem::Unparseable(e)),
+        Err(_) => return Err(CommitProblem::Missing(commit.hash)),
+    };
+    let (commit_id, key) = find!(
+        ctx,
+        (commit, key),
+        commit_ns::pattern!(ctx, tribles, [{commit @ ed25519_pubkey: key}])
+    )
+    .at_most_one()
+    .map_err(|_| ValidationError::AmbiguousSignature)
+    .and_then(|r| r.ok_or(ValidationError::MissingSignature))
+    .and_then(|r| r.map_err(ValidationError::BadValue))
+    .map_err(CommitProblem::BadSignature)?;
+    verify(tribles.clone(), commit_id).map_err(CommitProblem::BadSignature)?;
+    if !trusted.contains(&key.to_bytes()) {
+        return Err(CommitProblem::UntrustedKey(key));
+    }
+
+    let payload = payload(&tribles, commit_id).map_err(CommitProblem::BadSignature)?;
+    let blob = repo
+        .pull(payload.hash)
+        .await
+        .map_err(|_| CommitProblem::Missing(payload.hash))?;
+    if Hash::<Blake3>::digest(&blob) != payload.hash {
+        return Err(CommitProblem::BadPayload);
+    }
+    signed_parents(&tribles, commit_id).map_err(CommitProblem::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlobSet;
+    use anybytes::Bytes;
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+    use std::convert::TryInto;
+
+    NS! {
+        pub namespace notes {
+            "7B38B03F6B0E879BAE61F6FC4B4AFC27" as text: ShortString;
+        }
+    }
+
+    fn note(id: Id, text: &str) -> TribleSet {
+        notes::entity!(id, { text: text.try_into().unwrap() })
+    }
+
+    fn commit(
+        blobs: &mut BlobSet<Blake3>,
+        payload: &TribleSet,
+        parents: &[Handle<Blake3, SimpleArchive>],
+    ) -> Handle<Blake3, SimpleArchive> {
+        commit_with_key(blobs, payload, parents, [7; 32])
+    }
+
+    fn commit_with_key(
+        blobs: &mut BlobSet<Blake3>,
+        payload: &TribleSet,
+        parents: &[Handle<Blake3, SimpleArchive>],
+        key: [u8; 32],
+    ) -> Handle<Blake3, SimpleArchive> {
+        let payload = blobs.put(SimpleArchive::from(payload));
+        let builder = parents
+            .iter()
+            .fold(CommitBuilder::new(payload), |b, &p| b.parent(p));
+        let tribles = builder.sign(SigningKey::from_bytes(&key)).unwrap();
+        blobs.put(SimpleArchive::from(&tribles))
+    }
+
+    #[test]
+    fn history_and_merge() {
+        let (x, y) = (ufoid(), ufoid());
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+
+        let mut base = note(x, "x");
+        base.union(note(y, "y"));
+        let root = commit(&mut blobs, &base, &[]);
+
+        let mut ours = base.clone();
+        ours.union(note(ufoid(), "ours"));
+        let ours_commit = commit(&mut blobs, &ours, &[root]);
+
+        let mut theirs = note(x, "x");
+        theirs.union(note(ufoid(), "theirs"));
+        let theirs_commit = commit(&mut blobs, &theirs, &[root]);
+
+        let walked: Vec<_> = block_on(history(&blobs, ours_commit).try_collect::<Vec<_>>())
+            .unwrap()
+            .into_iter()
+            .map(|(commit, _)| commit)
+            .collect();
+        assert_eq!(walked, vec![ours_commit, root]);
+
+        assert_eq!(
+            block_on(merge_base(&blobs, ours_commit, theirs_commit)).unwrap(),
+            Some(root)
+        );
+
+        let merged = block_on(merge_commits(&blobs, ours_commit, theirs_commit)).unwrap();
+        let mut expected = ours.difference(&note(y, "y"));
+        expected.union(theirs);
+        assert_eq!(merged, expected);
+
+        let merge_commit = commit(&mut blobs, &merged, &[ours_commit, theirs_commit]);
+        let walked = block_on(history(&blobs, merge_commit).try_collect::<Vec<_>>()).unwrap();
+        assert_eq!(walked.len(), 4);
+    }
+
+    #[test]
+    fn merge_base_stops_early() {
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let root = commit(&mut blobs, &note(ufoid(), "root"), &[]);
+        let mut chain = vec![root];
+        for i in 0..8 {
+            let next = commit(&mut blobs, &note(ufoid(), &i.to_string()), &[chain[i]]);
+            chain.push(next);
+        }
+        let ours = commit(&mut blobs, &note(ufoid(), "ours"), &[chain[8]]);
+        let theirs = commit(&mut blobs, &note(ufoid(), "theirs"), &[chain[8]]);
+
+        // The walk never reaches the root, so it may as well be missing.
+        let mut pruned: BlobSet<Blake3> = BlobSet::new();
+        for (hash, blob) in blobs.iter_raw() {
+            if *hash != root.hash {
+                pruned.put_raw(blob.clone());
+            }
+        }
+        assert_eq!(
+            block_on(merge_base(&pruned, ours, theirs)).unwrap(),
+            Some(chain[8])
+        );
+    }
+
+    #[test]
+    fn verify_chain() {
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let root = commit(&mut blobs, &note(ufoid(), "root"), &[]);
+        let head = commit(&mut blobs, &note(ufoid(), "head"), &[root]);
+
+        let trusted = trust_ns::entity!({
+            trusted_key: SigningKey::from_bytes(&[7; 32]).verifying_key()
+        });
+        let report = block_on(verify_history(&blobs, head, &trusted)).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.verified, 2);
+
+        let report = block_on(verify_history(&blobs, head, &TribleSet::new())).unwrap();
+        assert_eq!(report.verified, 0);
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::UntrustedKey(_))) if bad == head
+        ));
+
+        let intruder = commit_with_key(&mut blobs, &note(ufoid(), "intruder"), &[head], [8; 32]);
+        let report = block_on(verify_history(&blobs, intruder, &trusted)).unwrap();
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::UntrustedKey(_))) if bad == intruder
+        ));
+
+        // Missing and malformed commits are reported rather than failing the walk.
+        let mut pruned: BlobSet<Blake3> = BlobSet::new();
+        for (hash, blob) in blobs.iter_raw() {
+            if *hash != root.hash {
+                pruned.put_raw(blob.clone());
+            }
+        }
+        let report = block_on(verify_history(&pruned, head, &trusted)).unwrap();
+        assert_eq!(report.verified, 1);
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::Missing(hash))) if bad == root && hash == root.hash
+        ));
+
+        let garbage = Bytes::from(vec![0u8; 7]);
+        let garbage: Handle<Blake3, SimpleArchive> =
+            unsafe { Handle::new(pruned.put_raw(garbage)) };
+        let report = block_on(verify_history(&pruned, garbage, &trusted)).unwrap();
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::Unparseable(_))) if bad == garbage
+        ));
+    }
+
+    #[test]
+    fn metadata_signature() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let payload = SimpleArchive::from(&note(ufoid(), "payload")).as_handle();
+        let tribles = CommitBuilder::new(payload)
+            .short_message("signed".try_into().unwrap())
+            .sign(key.clone())
+            .unwrap();
+        let (commit_id,) = find!(
+            ctx,
+            (commit,),
+            commit_ns::pattern!(ctx, tribles, [{commit @ tribles: (payload)}])
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+        assert!(verify(tribles.clone(), commit_id).is_ok());
+
+        let mut tampered = tribles.difference(&commit_ns::entity!(commit_id, {
+            short_message: "signed".try_into().unwrap()
+        }));
+        tampered.union(commit_ns::entity!(commit_id, {
+            short_message: "forged".try_into().unwrap()
+        }));
+        assert_eq!(
+            verify(tampered, commit_id),
+            Err(ValidationError::BadSignature)
+        );
+
+        // Commits signed before the metadata was covered.
+        let legacy_id = ufoid();
+        let signature = key.sign(&payload.hash.bytes);
+        let legacy = commit_ns::entity!(legacy_id, {
+            tribles: payload,
+            ed25519_pubkey: key.verifying_key(),
+            ed25519_signature_r: RComponent::from_signature(signature),
+            ed25519_signature_s: SComponent::from_signature(signature),
+        });
+        assert!(verify(legacy.clone(), legacy_id).is_ok());
+
+        // Legacy signatures don't cover parents, so an added one is rejected
+        // and not followed.
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let ancestor = commit(&mut blobs, &note(ufoid(), "ancestor"), &[]);
+        let mut forged = legacy;
+        forged.union(commit_ns::entity!(legacy_id, { parent: ancestor }));
+        assert_eq!(
+            verify(forged.clone(), legacy_id),
+            Err(ValidationError::UnsignedMetadata)
+        );
+
+        blobs.put(SimpleArchive::from(&note(ufoid(), "payload")));
+        let forged = blobs.put(SimpleArchive::from(&forged));
+        let walked = block_on(history(&blobs, forged).try_collect::<Vec<_>>()).unwrap();
+        assert_eq!(walked.len(), 1);
+
+        let trusted = trust_ns::entity!({ trusted_key: key.verifying_key() });
+        let report = block_on(verify_history(&blobs, forged, &trusted)).unwrap();
+        assert_eq!(report.verified, 0);
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::BadSignature(ValidationError::UnsignedMetadata)))
+                if bad == forged
+        ));
+    }
+
+    #[test]
+    fn foreign_entities() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let ancestor = commit(&mut blobs, &note(ufoid(), "ancestor"), &[]);
+        let payload = blobs.put(SimpleArchive::from(&note(ufoid(), "payload")));
+
+        // A legacy commit, with a second entity that claims a signature
+        // version and a parent on its behalf.
+        let (legacy_id, foreign_id) = (ufoid(), ufoid());
+        let signature = key.sign(&payload.hash.bytes);
+        let mut forged = commit_ns::entity!(legacy_id, {
+            tribles: payload,
+            ed25519_pubkey: key.verifying_key(),
+            ed25519_signature_r: RComponent::from_signature(signature),
+            ed25519_signature_s: SComponent::from_signature(signature),
+        });
+        forged.union(commit_ns::entity!(foreign_id, {
+            signature_version: METADATA_SIGNATURE_V1,
+            parent: ancestor,
+        }));
+        assert_eq!(
+            verify(forged.clone(), legacy_id),
+            Err(ValidationError::ForeignEntity(foreign_id))
+        );
+        assert_eq!(signed_parents(&forged, legacy_id), Ok(vec![]));
+
+        let forged = blobs.put(SimpleArchive::from(&forged));
+        let walked = block_on(history(&blobs, forged).try_collect::<Vec<_>>()).unwrap();
+        assert_eq!(walked.len(), 1);
+
+        let trusted = trust_ns::entity!({ trusted_key: key.verifying_key() });
+        let report = block_on(verify_history(&blobs, forged, &trusted)).unwrap();
+        assert_eq!(report.verified, 0);
+        assert!(matches!(
+            report.first_bad,
+            Some((bad, CommitProblem::BadSignature(ValidationError::ForeignEntity(id))))
+                if bad == forged && id == foreign_id
+        ));
+    }
 }
@@ -55,6 +55,17 @@ macro_rules! pattern_inner {
             $constraints.push(Box::new($set.pattern($EntityId, a_var, v_var)));
         }
 
+    };
+    (@triple ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident, $FieldName:ident, _)) => {
+        {
+            use $crate::query::TriblePattern;
+            use $Namespace as ns;
+            let a_var: $crate::query::Variable<$crate::Id> = $ctx.next_variable();
+            let v_var: $crate::query::Variable<ns::types::$FieldName> = $ctx.next_variable();
+            $constraints.push(Box::new(a_var.is(ns::ids::$FieldName)));
+            $constraints.push(Box::new($set.pattern($EntityId, a_var, v_var)));
+        }
+
     };
     (@triple ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident, $FieldName:ident, $Value:expr)) => {
         {
@@ -68,32 +79,89 @@ macro_rules! pattern_inner {
 
     };
 
-    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {($EntityId:expr) @ $($FieldName:ident : $Value:tt),* $(,)?})) => {
+    (@fields ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident)) => {};
+    (@fields ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident) , $($rest:tt)*) => {
+        pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, $EntityId) $($rest)*);
+    };
+    (@fields ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident) ? $FieldName:ident : $Var:ident $($rest:tt)*) => {
+        {
+            use $crate::query::TriblePattern;
+            use $Namespace as ns;
+            let start = $ctx.next_index;
+            let a_var: $crate::query::Variable<$crate::Id> = $ctx.next_variable();
+            let mut local = $crate::query::VariableSet::new_empty();
+            for index in start..$ctx.next_index {
+                local.set(index);
+            }
+            let v_var: $crate::query::Variable<ns::types::$FieldName> = $Var;
+            let optional = $crate::query::VariableSet::new_singleton(v_var.index);
+            let triple: Vec<Box<dyn $crate::query::Constraint>> = vec![
+                Box::new(a_var.is(ns::ids::$FieldName)),
+                Box::new($set.pattern($EntityId, a_var, v_var)),
+            ];
+            $constraints.push(Box::new($crate::query::OptionalConstraint::new(
+                local,
+                optional,
+                Box::new($crate::query::IntersectionConstraint::new(triple)),
+            )));
+        }
+        pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, $EntityId) $($rest)*);
+    };
+    (@fields ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, $EntityId:ident) $FieldName:ident : $Value:tt $($rest:tt)*) => {
+        pattern_inner!(@triple ($constraints, $ctx, $set, $Namespace, $EntityId, $FieldName, $Value));
+        pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, $EntityId) $($rest)*);
+    };
+
+    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {($EntityId:expr) @ $($Fields:tt)*})) => {
         {
             let e_var: $crate::query::Variable<$crate::Id> = $ctx.next_variable();
             $constraints.push({ let e: $crate::Id = $EntityId; Box::new(e_var.is(e))});
-            $(pattern_inner!(@triple ($constraints, $ctx, $set, $Namespace, e_var, $FieldName, $Value));)*
+            pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, e_var) $($Fields)*);
         }
     };
 
-    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {$EntityId:ident @ $($FieldName:ident : $Value:tt),* $(,)?})) => {
+    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {$EntityId:ident @ $($Fields:tt)*})) => {
         {
             let e_var: $crate::query::Variable<$crate::Id> = $EntityId;
-            $(pattern_inner!(@triple ($constraints, $ctx, $set, $Namespace, e_var, $FieldName, $Value));)*
+            pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, e_var) $($Fields)*);
         }
     };
 
-    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {$($FieldName:ident : $Value:tt),*})) => {
+    (@entity ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path, {$($Fields:tt)*})) => {
         {
             let e_var: $crate::query::Variable<$crate::Id> = $ctx.next_variable();
-            $(pattern_inner!(@triple ($constraints, $ctx, $set, $Namespace, e_var, $FieldName, $Value));)*
+            pattern_inner!(@fields ($constraints, $ctx, $set, $Namespace, e_var) $($Fields)*);
+        }
+    };
+    (@entities ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path)) => {};
+    (@entities ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path) , $($rest:tt)*) => {
+        pattern_inner!(@entities ($constraints, $ctx, $set, $Namespace) $($rest)*);
+    };
+    (@entities ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path) ! $Entity:tt $($rest:tt)*) => {
+        {
+            let start = $ctx.next_index;
+            let mut negated: Vec<Box<dyn $crate::query::Constraint>> = vec!();
+            pattern_inner!(@entity (negated, $ctx, $set, $Namespace, $Entity));
+            let mut local = $crate::query::VariableSet::new_empty();
+            for index in start..$ctx.next_index {
+                local.set(index);
+            }
+            $constraints.push(Box::new($crate::query::NotConstraint::new(
+                local,
+                Box::new($crate::query::IntersectionConstraint::new(negated)),
+            )));
         }
+        pattern_inner!(@entities ($constraints, $ctx, $set, $Namespace) $($rest)*);
     };
-    ($Namespace:path, $ctx:ident, $set:expr, [$($Entity:tt),*]) => {
+    (@entities ($constraints:ident, $ctx:ident, $set:ident, $Namespace:path) $Entity:tt $($rest:tt)*) => {
+        pattern_inner!(@entity ($constraints, $ctx, $set, $Namespace, $Entity));
+        pattern_inner!(@entities ($constraints, $ctx, $set, $Namespace) $($rest)*);
+    };
+    ($Namespace:path, $ctx:ident, $set:expr, [$($Entities:tt)*]) => {
         {
             let set = &($set);
             let mut constraints: Vec<Box<dyn $crate::query::Constraint>> = vec!();
-            $(pattern_inner!(@entity (constraints, $ctx, set, $Namespace, $Entity));)*
+            pattern_inner!(@entities (constraints, $ctx, set, $Namespace) $($Entities)*);
             $crate::query::IntersectionConstraint::new(constraints)
         }
     };
@@ -145,6 +213,13 @@ pub use hex_literal;
 ///
 /// this allows you to access attribute ids and types via their human readable names, e.g.
 /// `namespace_name::ids::attrName` and `namespace_name::types::attrName`.
+///
+/// Within `pattern!`, an attribute value of `_` matches any value,
+/// and an entity prefixed with `!` excludes matches of that entity,
+/// e.g. `[{e @ name: name}, !{e @ loves: _}]` finds named entities without a lover.
+/// Attributes prefixed with `?` are optional, e.g. `{e @ name: name, ?title: title}`
+/// also finds entities without a title, which [crate::query::find] returns as `None`
+/// when `title` is prefixed with `?` there too.
 #[macro_export]
 macro_rules! NS {
     ($visibility:vis namespace $mod_name:ident {$($FieldId:literal as $FieldName:ident: $FieldType:ty;)*}) => {
@@ -217,7 +292,7 @@ pub use NS;
 mod tests {
     use fake::{faker::name::raw::Name, locales::EN, Fake};
 
-    use crate::{query::find, types::ShortString, ufoid, Id, TribleSet};
+    use crate::{query::{find, or}, types::ShortString, ufoid, Id, TribleSet};
 
     use std::convert::TryInto;
 
@@ -311,6 +386,171 @@ mod tests {
         assert_eq!(vec![Ok((juliet, "Juliet".try_into().unwrap(),))], r);
     }
 
+    #[test]
+    fn ns_pattern_not() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+
+        kb.union(knights::entity!(juliet,
+        {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo,
+            title: "Maiden".try_into().unwrap()
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet,
+            title: "Prince".try_into().unwrap()
+        }));
+        kb.union(knights::entity!({
+            name: "Angelica".try_into().unwrap(),
+            title: "Nurse".try_into().unwrap()
+        }));
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e, name),
+            knights::pattern!(ctx, kb, [
+            {e @
+                name: name},
+            !{e @
+                loves: _
+            }])
+        )
+        .map(|r| r.map(|(_, name)| name))
+        .collect();
+        assert_eq!(vec![Ok("Angelica".try_into().unwrap())], r);
+
+        let paris = ufoid();
+        kb.union(knights::entity!(paris, {
+            name: "Paris".try_into().unwrap(),
+            loves: juliet
+        }));
+
+        let r: Vec<_> = find!(
+            ctx,
+            (lover, beloved),
+            knights::pattern!(ctx, kb, [
+            {lover @
+                loves: beloved},
+            !{beloved @
+                loves: lover
+            }])
+        )
+        .collect();
+        assert_eq!(vec![Ok((paris, juliet))], r);
+    }
+
+    #[test]
+    fn ns_pattern_optional() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+
+        kb.union(knights::entity!(juliet,
+        {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo,
+            title: "Maiden".try_into().unwrap()
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet
+        }));
+
+        let mut r: Vec<_> = find!(
+            ctx,
+            (e, name, ?title, ?lover),
+            knights::pattern!(ctx, kb, [
+            {e @
+                name: name,
+                ?title: title,
+                ?loves: lover
+            }])
+        )
+        .map(|r| r.unwrap())
+        .map(|(_, name, title, lover)| (String::from(&name), title.map(|t| String::from(&t)), lover))
+        .collect();
+        r.sort();
+        assert_eq!(
+            vec![
+                ("Juliet".to_string(), Some("Maiden".to_string()), Some(romeo)),
+                ("Romeo".to_string(), None, Some(juliet)),
+            ],
+            r
+        );
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e, ?title),
+            knights::pattern!(ctx, kb, [
+            {e @
+                name: ("Romeo".try_into().unwrap()),
+                ?title: title
+            }])
+        )
+        .collect();
+        assert_eq!(vec![Ok((romeo, None))], r);
+
+        // Without the `?` in find!, entities without a title are skipped.
+        let r: Vec<_> = find!(
+            ctx,
+            (e, title),
+            knights::pattern!(ctx, kb, [
+            {e @
+                name: _,
+                ?title: title
+            }])
+        )
+        .collect();
+        assert_eq!(vec![Ok((juliet, "Maiden".try_into().unwrap()))], r);
+    }
+
+    #[test]
+    fn ns_pattern_or() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+
+        kb.union(knights::entity!(juliet,
+        {
+            name: "Juliet".try_into().unwrap(),
+            title: "Maiden".try_into().unwrap()
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            title: "Prince".try_into().unwrap()
+        }));
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e, label),
+            or!(ctx,
+                knights::pattern!(ctx, kb, [{e @ name: label}]),
+                knights::pattern!(ctx, kb, [{e @ title: label}])
+            )
+            .unwrap()
+        )
+        .collect();
+        assert_eq!(r.len(), 4);
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e),
+            or!(ctx,
+                knights::pattern!(ctx, kb, [{e @ name: ("Prince".try_into().unwrap())}]),
+                knights::pattern!(ctx, kb, [{e @ title: ("Prince".try_into().unwrap())}])
+            )
+            .unwrap()
+        )
+        .collect();
+        assert_eq!(vec![Ok((romeo,))], r);
+    }
+
     #[test]
     fn ns_pattern_large() {
         let mut kb = TribleSet::new();
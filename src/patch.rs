@@ -107,6 +107,53 @@ pub(crate) enum Body<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmen
     Branch(*mut Branch<KEY_LEN, O, S, [Option<Head<KEY_LEN, O, S>>]>),
 }
 
+/// The bounds of a range scan over infixes, see [Head::infixes_range].
+///
+/// `lower` and `upper` track whether the infix bytes seen so far are
+/// still equal to those of `min` and `max` respectively.
+#[derive(Copy, Clone)]
+pub(crate) struct InfixBounds<'a, const INFIX_LEN: usize> {
+    min: &'a [u8; INFIX_LEN],
+    max: &'a [u8; INFIX_LEN],
+    lower: bool,
+    upper: bool,
+}
+
+impl<'a, const INFIX_LEN: usize> InfixBounds<'a, INFIX_LEN> {
+    fn new(min: &'a [u8; INFIX_LEN], max: &'a [u8; INFIX_LEN]) -> Self {
+        InfixBounds {
+            min,
+            max,
+            lower: true,
+            upper: true,
+        }
+    }
+
+    /// Narrows the bounds to the infixes with `byte` at `index`,
+    /// returning false if there are no such infixes within them.
+    fn narrow(&mut self, index: usize, byte: u8) -> bool {
+        if self.lower {
+            if byte < self.min[index] {
+                return false;
+            }
+            self.lower = byte == self.min[index];
+        }
+        if self.upper {
+            if byte > self.max[index] {
+                return false;
+            }
+            self.upper = byte == self.max[index];
+        }
+        true
+    }
+
+    /// Returns true if every infix starting with the bytes seen so far
+    /// lies within the bounds.
+    fn covers(&self) -> bool {
+        !self.lower && !self.upper
+    }
+}
+
 #[repr(C)]
 pub(crate) struct Head<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>> {
     tptr: std::ptr::NonNull<u8>,
@@ -523,6 +570,96 @@ impl<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>>
         }
     }
 
+    /// Like [Head::infixes], but only visits infixes that lie within
+    /// `bounds`, pruning all subtrees outside of them.
+    pub(crate) fn infixes_range<const PREFIX_LEN: usize, const INFIX_LEN: usize, F>(
+        &self,
+        prefix: &[u8; PREFIX_LEN],
+        mut bounds: InfixBounds<'_, INFIX_LEN>,
+        at_depth: usize,
+        f: &mut F,
+    ) where
+        F: FnMut([u8; INFIX_LEN]),
+    {
+        let node_end_depth = self.end_depth();
+        let leaf_key = self.leaf_key();
+        for depth in at_depth..std::cmp::min(node_end_depth, PREFIX_LEN) {
+            if leaf_key[O::key_index(depth)] != prefix[depth] {
+                return;
+            }
+        }
+        for depth in std::cmp::max(at_depth, PREFIX_LEN)
+            ..std::cmp::min(node_end_depth, PREFIX_LEN + INFIX_LEN)
+        {
+            if !bounds.narrow(depth - PREFIX_LEN, leaf_key[O::key_index(depth)]) {
+                return;
+            }
+        }
+
+        if bounds.covers() {
+            // The entire subtree lies within the range.
+            return self.infixes(prefix, at_depth, f);
+        }
+        if PREFIX_LEN + INFIX_LEN <= node_end_depth {
+            let infix = leaf_key
+                [O::key_index(PREFIX_LEN)..=O::key_index(PREFIX_LEN + INFIX_LEN - 1)]
+                .try_into()
+                .expect("invalid infix range");
+            f(infix);
+            return;
+        }
+        if PREFIX_LEN > node_end_depth {
+            if let Some(child) = self.child(prefix[node_end_depth]) {
+                child.infixes_range(prefix, bounds, node_end_depth, f);
+            }
+            return;
+        }
+        for child in self.iter_children().filter_map(|c| c.as_ref()) {
+            child.infixes_range(prefix, bounds, node_end_depth, f);
+        }
+    }
+
+    /// Like [Head::segmented_len], but only counts the segments
+    /// that lie within `bounds`, see [Head::infixes_range].
+    pub(crate) fn segmented_len_range<const PREFIX_LEN: usize, const INFIX_LEN: usize>(
+        &self,
+        prefix: &[u8; PREFIX_LEN],
+        mut bounds: InfixBounds<'_, INFIX_LEN>,
+        at_depth: usize,
+    ) -> u64 {
+        let node_end_depth = self.end_depth();
+        let leaf_key = self.leaf_key();
+        for depth in at_depth..std::cmp::min(node_end_depth, PREFIX_LEN) {
+            if leaf_key[O::key_index(depth)] != prefix[depth] {
+                return 0;
+            }
+        }
+        for depth in std::cmp::max(at_depth, PREFIX_LEN)
+            ..std::cmp::min(node_end_depth, PREFIX_LEN + INFIX_LEN)
+        {
+            if !bounds.narrow(depth - PREFIX_LEN, leaf_key[O::key_index(depth)]) {
+                return 0;
+            }
+        }
+
+        if bounds.covers() {
+            return self.segmented_len(at_depth, prefix);
+        }
+        if PREFIX_LEN + INFIX_LEN <= node_end_depth {
+            return 1;
+        }
+        if PREFIX_LEN > node_end_depth {
+            return match self.child(prefix[node_end_depth]) {
+                Some(child) => child.segmented_len_range(prefix, bounds, node_end_depth),
+                None => 0,
+            };
+        }
+        self.iter_children()
+            .filter_map(|c| c.as_ref())
+            .map(|child| child.segmented_len_range(prefix, bounds, node_end_depth))
+            .sum()
+    }
+
     pub(crate) fn union(&mut self, other: Self, at_depth: usize) {
         let self_hash = self.hash();
         let other_hash = other.hash();
@@ -578,6 +715,175 @@ impl<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>>
         }
     }
 
+    pub(crate) fn difference(&self, other: &Self, at_depth: usize) -> Option<Self> {
+        if self.hash() == other.hash() {
+            return None;
+        }
+        let self_depth = self.end_depth();
+        let other_depth = other.end_depth();
+
+        let self_key = self.leaf_key();
+        let other_key = other.leaf_key();
+        for depth in at_depth..std::cmp::min(self_depth, other_depth) {
+            let i = O::key_index(depth);
+            if self_key[i] != other_key[i] {
+                return Some(self.clone());
+            }
+        }
+
+        if self_depth == KEY_LEN && other_depth == KEY_LEN {
+            return None;
+        }
+
+        if self_depth < other_depth {
+            let key = other_key[O::key_index(self_depth)];
+            if self.child(key).is_none() {
+                return Some(self.clone());
+            }
+            let children = self
+                .iter_children()
+                .filter_map(|c| c.as_ref())
+                .filter_map(|c| {
+                    if c.key() == key {
+                        c.difference(other, self_depth)
+                    } else {
+                        Some(c.clone())
+                    }
+                })
+                .collect();
+            return Self::from_children(at_depth, self_depth, children);
+        }
+
+        if other_depth < self_depth {
+            let key = self_key[O::key_index(other_depth)];
+            return match other.child(key) {
+                Some(other_child) => self
+                    .difference(other_child, other_depth)
+                    .map(|head| head.with_start(at_depth)),
+                None => Some(self.clone()),
+            };
+        }
+
+        let mut changed = false;
+        let mut children = Vec::new();
+        for child in self.iter_children().filter_map(|c| c.as_ref()) {
+            if let Some(other_child) = other.child(child.key()) {
+                match child.difference(other_child, self_depth) {
+                    Some(diff) => {
+                        changed |= diff.hash() != child.hash();
+                        children.push(diff);
+                    }
+                    None => changed = true,
+                }
+            } else {
+                children.push(child.clone());
+            }
+        }
+        if !changed {
+            return Some(self.clone());
+        }
+        Self::from_children(at_depth, self_depth, children)
+    }
+
+    pub(crate) fn intersect(&self, other: &Self, at_depth: usize) -> Option<Self> {
+        if self.hash() == other.hash() {
+            return Some(self.clone());
+        }
+        let self_depth = self.end_depth();
+        let other_depth = other.end_depth();
+
+        let self_key = self.leaf_key();
+        let other_key = other.leaf_key();
+        for depth in at_depth..std::cmp::min(self_depth, other_depth) {
+            let i = O::key_index(depth);
+            if self_key[i] != other_key[i] {
+                return None;
+            }
+        }
+
+        if self_depth == KEY_LEN && other_depth == KEY_LEN {
+            return Some(self.clone());
+        }
+
+        if self_depth < other_depth {
+            let key = other_key[O::key_index(self_depth)];
+            return self
+                .child(key)?
+                .intersect(other, self_depth)
+                .map(|head| head.with_start(at_depth));
+        }
+
+        if other_depth < self_depth {
+            let key = self_key[O::key_index(other_depth)];
+            return self
+                .intersect(other.child(key)?, other_depth)
+                .map(|head| head.with_start(at_depth));
+        }
+
+        let children = self
+            .iter_children()
+            .filter_map(|c| c.as_ref())
+            .filter_map(|child| {
+                let other_child = other.child(child.key())?;
+                child.intersect(other_child, self_depth)
+            })
+            .collect();
+        Self::from_children(at_depth, self_depth, children)
+    }
+
+    /// Builds the subtree containing `entries` bottom up, which must be sorted
+    /// in tree order, free of duplicates, and share their keys up to `at_depth`.
+    pub(crate) fn from_sorted(at_depth: usize, entries: &[Entry<KEY_LEN>]) -> Option<Self> {
+        let (first, rest) = entries.split_first()?;
+        let Some(last) = rest.last() else {
+            return Some(first.leaf::<O, S>().with_start(at_depth));
+        };
+        let first_key = first.key();
+        let last_key = last.key();
+        // Sorted keys share the prefix that the first and last key share.
+        let end_depth = (at_depth..KEY_LEN)
+            .find(|&depth| first_key[O::key_index(depth)] != last_key[O::key_index(depth)])
+            .expect("duplicate entries");
+        let i = O::key_index(end_depth);
+        let mut children = Vec::new();
+        let mut rest = entries;
+        while let Some(entry) = rest.first() {
+            let byte = entry.key()[i];
+            let (group, tail) = rest.split_at(rest.partition_point(|e| e.key()[i] == byte));
+            children.extend(Self::from_sorted(end_depth, group));
+            rest = tail;
+        }
+        Self::from_children(at_depth, end_depth, children)
+    }
+
+    /// Creates a new node branching at `end_depth` from the provided children,
+    /// all of which must share the same prefix up to `end_depth`.
+    /// Degenerate nodes are avoided, so no children result in `None`,
+    /// and a single child is returned in place of the node.
+    pub(crate) fn from_children(
+        at_depth: usize,
+        end_depth: usize,
+        mut children: Vec<Self>,
+    ) -> Option<Self> {
+        let first = children.pop()?;
+        if children.is_empty() {
+            return Some(first.with_start(at_depth));
+        }
+
+        let key = first.leaf_key()[O::key_index(at_depth)];
+        let mut head = Branch::<KEY_LEN, O, S, [Option<Head<KEY_LEN, O, S>>; 2]>::new(
+            key,
+            end_depth,
+            first.with_start(end_depth),
+        );
+        for child in children {
+            unsafe {
+                head.upsert(child, |_, _| unreachable!());
+            }
+        }
+        Some(head)
+    }
+
     pub(crate) fn take_or_clone_children<F>(&self, f: F)
     where
         F: FnMut(Self),
@@ -602,6 +908,15 @@ impl<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>>
             }
         }
     }
+
+    pub(crate) fn child(&self, key: u8) -> Option<&Head<KEY_LEN, O, S>> {
+        unsafe {
+            match self.body() {
+                Body::Leaf(_) => panic!("child on leaf"),
+                Body::Branch(branch) => (*branch).child_table.table_get(key),
+            }
+        }
+    }
 }
 
 unsafe impl<const KEY_LEN: usize, O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>> ByteEntry
@@ -733,6 +1048,29 @@ where
         }
     }
 
+    /// Builds the tree bottom up from `entries`, which must be sorted by their
+    /// keys in tree order, see [KeyOrdering::tree_ordered], and free of duplicates.
+    /// Otherwise the tree is corrupt, so only validated entries may be passed,
+    /// and [PATCH::from_entries] is the public way in.
+    ///
+    /// This avoids descending from the root for each entry, like inserting them one by one.
+    pub(crate) fn from_sorted(entries: &[Entry<KEY_LEN>]) -> Self {
+        init();
+        debug_assert!(entries
+            .windows(2)
+            .all(|w| O::tree_ordered(w[0].key()) < O::tree_ordered(w[1].key())));
+        PATCH {
+            root: Head::from_sorted(0, entries),
+        }
+    }
+
+    /// Sorts and deduplicates `entries` and builds the tree with [PATCH::from_sorted].
+    pub fn from_entries(mut entries: Vec<Entry<KEY_LEN>>) -> Self {
+        entries.sort_by_cached_key(|entry| O::tree_ordered(entry.key()));
+        entries.dedup_by(|a, b| a.key() == b.key());
+        Self::from_sorted(&entries)
+    }
+
     pub fn len(&self) -> u64 {
         if let Some(root) = &self.root {
             root.count()
@@ -749,7 +1087,10 @@ where
         F: FnMut([u8; INFIX_LEN]),
     {
         assert!(PREFIX_LEN + INFIX_LEN <= KEY_LEN);
-        assert!(S::segment(PREFIX_LEN) == S::segment(PREFIX_LEN + INFIX_LEN - 1));
+        assert!(
+            S::segment(O::key_index(PREFIX_LEN))
+                == S::segment(O::key_index(PREFIX_LEN + INFIX_LEN - 1))
+        );
         if let Some(root) = &self.root {
             root.infixes(prefix, 0, &mut f);
         }
@@ -771,6 +1112,43 @@ where
         }
     }
 
+    /// Calls `f` for every infix after `prefix` that lies within `min..=max`,
+    /// compared lexicographically, without visiting the infixes outside of it.
+    pub fn infixes_range<const PREFIX_LEN: usize, const INFIX_LEN: usize, F>(
+        &self,
+        prefix: &[u8; PREFIX_LEN],
+        min: &[u8; INFIX_LEN],
+        max: &[u8; INFIX_LEN],
+        mut f: F,
+    ) where
+        F: FnMut([u8; INFIX_LEN]),
+    {
+        assert!(PREFIX_LEN + INFIX_LEN <= KEY_LEN);
+        assert!(
+            S::segment(O::key_index(PREFIX_LEN))
+                == S::segment(O::key_index(PREFIX_LEN + INFIX_LEN - 1))
+        );
+        if let Some(root) = &self.root {
+            root.infixes_range(prefix, InfixBounds::new(min, max), 0, &mut f);
+        }
+    }
+
+    /// Counts the distinct segments after `prefix` that lie within `min..=max`.
+    /// The range has to span the entire segment.
+    pub fn segmented_len_range<const PREFIX_LEN: usize, const INFIX_LEN: usize>(
+        &self,
+        prefix: &[u8; PREFIX_LEN],
+        min: &[u8; INFIX_LEN],
+        max: &[u8; INFIX_LEN],
+    ) -> u64 {
+        assert!(PREFIX_LEN + INFIX_LEN <= KEY_LEN);
+        if let Some(root) = &self.root {
+            root.segmented_len_range(prefix, InfixBounds::new(min, max), 0)
+        } else {
+            0
+        }
+    }
+
     pub fn iter_prefix<'a, const PREFIX_LEN: usize>(
         &'a self,
     ) -> PATCHPrefixIterator<'a, KEY_LEN, PREFIX_LEN, O, S> {
@@ -786,6 +1164,37 @@ where
             }
         }
     }
+
+    pub fn remove(&mut self, key: &[u8; KEY_LEN]) {
+        if let Some(root) = &self.root {
+            let leaf: Head<KEY_LEN, O, S> = Entry::new(key).leaf();
+            self.root = root.difference(&leaf, 0);
+        }
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other)) => PATCH {
+                root: root.difference(other, 0),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other)) => PATCH {
+                root: root.intersect(other, 0),
+            },
+            _ => PATCH { root: None },
+        }
+    }
+
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        let mut result = self.difference(other);
+        result.union(other.difference(self));
+        result
+    }
 }
 
 impl<const KEY_LEN: usize, O, S> PartialEq for PATCH<KEY_LEN, O, S>
@@ -1142,6 +1551,34 @@ mod tests {
         prop_assert_eq!(set_vec, tree_vec);
     }
 
+    #[test]
+    fn tree_infixes_range(keys in prop::collection::vec(prop::collection::vec(0u8..=3, 64), 1..1024),
+                          a in prop::collection::vec(0u8..=3, 64),
+                          b in prop::collection::vec(0u8..=3, 64)) {
+        let a: [u8; 64] = a.try_into().unwrap();
+        let b: [u8; 64] = b.try_into().unwrap();
+        let (min, max) = if a <= b { (a, b) } else { (b, a) };
+
+        let mut tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
+        let mut set = HashSet::new();
+        for key in keys {
+            let key: [u8; 64] = key.try_into().unwrap();
+            tree.insert(&Entry::new(&key));
+            if min <= key && key <= max {
+                set.insert(key);
+            }
+        }
+        let mut set_vec = Vec::from_iter(set.into_iter());
+        let mut tree_vec = vec![];
+        tree.infixes_range(&[0; 0], &min, &max, |x| tree_vec.push(x));
+
+        set_vec.sort();
+        tree_vec.sort();
+
+        prop_assert_eq!(set_vec.len() as u64, tree.segmented_len_range(&[0; 0], &min, &max));
+        prop_assert_eq!(set_vec, tree_vec);
+    }
+
     #[test]
     fn tree_iter(keys in prop::collection::vec(prop::collection::vec(0u8..255, 64), 1..1024)) {
         let mut tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
@@ -1224,5 +1661,104 @@ mod tests {
 
         prop_assert_eq!(set_vec, tree_vec);
         }
+
+        #[test]
+    fn tree_difference(left in prop::collection::vec(prop::collection::vec(0u8..=255, 64), 1..1024),
+                       right in prop::collection::vec(prop::collection::vec(0u8..=255, 64), 1..1024)) {
+        let mut left_set = HashSet::new();
+        let mut left_tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
+        for entry in &left {
+            let key: [u8; 64] = entry[..].try_into().unwrap();
+            left_tree.insert(&Entry::new(&key));
+            left_set.insert(key);
+        }
+
+        let mut right_set = HashSet::new();
+        let mut right_tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
+        // Share some keys so that the difference isn't trivial.
+        for entry in right.iter().chain(left.iter().step_by(2)) {
+            let key: [u8; 64] = entry[..].try_into().unwrap();
+            right_tree.insert(&Entry::new(&key));
+            right_set.insert(key);
+        }
+
+        let diff_tree = left_tree.difference(&right_tree);
+        let mut set_vec = Vec::from_iter(left_set.difference(&right_set).copied());
+        let mut tree_vec = vec![];
+        diff_tree.infixes(&[0; 0], &mut |x| tree_vec.push(x));
+        set_vec.sort();
+        tree_vec.sort();
+        prop_assert_eq!(set_vec.len() as u64, diff_tree.len());
+        prop_assert_eq!(set_vec, tree_vec);
+
+        let inter_tree = left_tree.intersection(&right_tree);
+        let mut set_vec = Vec::from_iter(left_set.intersection(&right_set).copied());
+        let mut tree_vec = vec![];
+        inter_tree.infixes(&[0; 0], &mut |x| tree_vec.push(x));
+        set_vec.sort();
+        tree_vec.sort();
+        prop_assert_eq!(set_vec.len() as u64, inter_tree.len());
+        prop_assert_eq!(set_vec, tree_vec);
+
+        let sym_tree = left_tree.symmetric_difference(&right_tree);
+        let mut set_vec = Vec::from_iter(left_set.symmetric_difference(&right_set).copied());
+        let mut tree_vec = vec![];
+        sym_tree.infixes(&[0; 0], &mut |x| tree_vec.push(x));
+        set_vec.sort();
+        tree_vec.sort();
+        prop_assert_eq!(set_vec.len() as u64, sym_tree.len());
+        prop_assert_eq!(set_vec, tree_vec);
+        }
+
+        #[test]
+    fn tree_from_entries(keys in prop::collection::vec(prop::collection::vec(0u8..=3, 64), 1..1024)) {
+        let mut tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
+        let mut entries = vec![];
+        for key in &keys {
+            let key: [u8; 64] = key[..].try_into().unwrap();
+            tree.insert(&Entry::new(&key));
+            entries.push(Entry::new(&key));
+        }
+
+        let bulk = PATCH::<64, IdentityOrder, SingleSegmentation>::from_entries(entries);
+        prop_assert_eq!(bulk.len(), tree.len());
+        prop_assert!(bulk == tree);
+        let mut tree_vec = vec![];
+        tree.infixes(&[0; 0], &mut |x: [u8; 64]| tree_vec.push(x));
+        let mut bulk_vec = vec![];
+        bulk.infixes(&[0; 0], &mut |x: [u8; 64]| bulk_vec.push(x));
+        tree_vec.sort();
+        bulk_vec.sort();
+        prop_assert_eq!(bulk_vec, tree_vec);
+        }
+
+        #[test]
+    fn tree_remove(keys in prop::collection::vec(prop::collection::vec(0u8..=255, 64), 1..1024)) {
+        let mut set = HashSet::new();
+        let mut tree = PATCH::<64, IdentityOrder, SingleSegmentation>::new();
+        for key in &keys {
+            let key: [u8; 64] = key[..].try_into().unwrap();
+            tree.insert(&Entry::new(&key));
+            set.insert(key);
+        }
+        let snapshot = tree.clone();
+
+        for key in keys.iter().step_by(2) {
+            let key: [u8; 64] = key[..].try_into().unwrap();
+            tree.remove(&key);
+            set.remove(&key);
+        }
+
+        let mut set_vec = Vec::from_iter(set.into_iter());
+        let mut tree_vec = vec![];
+        tree.infixes(&[0; 0], &mut |x| tree_vec.push(x));
+        set_vec.sort();
+        tree_vec.sort();
+        prop_assert_eq!(set_vec.len() as u64, tree.len());
+        prop_assert_eq!(set_vec, tree_vec);
+
+        // Removal must not affect other versions sharing the structure.
+        prop_assert_eq!(snapshot.len(), keys.iter().collect::<HashSet<_>>().len() as u64);
+        }
     }
 }
@@ -6,14 +6,25 @@ pub struct Entry<const KEY_LEN: usize> {
     ptr: *mut Leaf<KEY_LEN>,
 }
 
+// Leaves are immutable and reference counted atomically.
+unsafe impl<const KEY_LEN: usize> Send for Entry<KEY_LEN> {}
+unsafe impl<const KEY_LEN: usize> Sync for Entry<KEY_LEN> {}
+
 impl<const KEY_LEN: usize> Entry<KEY_LEN> {
     pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        // Entries can be created before any tree, e.g. for bulk loading,
+        // and their hash has to use the same key as the trees they end up in.
+        init();
         unsafe {
             let ptr = Leaf::<KEY_LEN>::new(key);
             Self { ptr }
         }
     }
 
+    pub fn key(&self) -> &[u8; KEY_LEN] {
+        unsafe { &(*self.ptr).key }
+    }
+
     pub(super) fn leaf<O: KeyOrdering<KEY_LEN>, S: KeySegmentation<KEY_LEN>>(
         &self,
     ) -> Head<KEY_LEN, O, S> {
@@ -10,25 +10,38 @@
 //! sub-languages, and data-sources can be composed.
 //!
 //!
+pub mod aggregate;
 pub mod constantconstraint;
 pub mod hashsetconstraint;
 pub mod intersectionconstraint;
 pub mod mask;
+pub mod notconstraint;
+pub mod optionalconstraint;
 pub mod patchconstraint;
+pub mod unionconstraint;
+pub mod valuerange;
+pub mod variableset;
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::Hash;
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 
+pub use aggregate::*;
 pub use constantconstraint::*;
 pub use hashsetconstraint::*;
 pub use intersectionconstraint::*;
 pub use mask::*;
+pub use notconstraint::*;
+pub use optionalconstraint::*;
 pub use patchconstraint::*;
+pub use unionconstraint::*;
+pub use valuerange::*;
+pub use variableset::*;
 
 use crate::{Id, Value, ValueParseError, Valuelike};
 
-use crate::bitset::ByteBitset;
 
 pub trait TriblePattern {
     type PatternConstraint<'a, V>: Constraint<'a>
@@ -46,8 +59,7 @@ pub trait TriblePattern {
         V: Valuelike;
 }
 
-pub type VariableId = u8;
-pub type VariableSet = ByteBitset;
+pub type VariableId = usize;
 
 #[derive(Debug)]
 pub struct VariableContext {
@@ -102,6 +114,12 @@ where
     pub fn extract(self, binding: &Binding) -> Result<T, crate::ValueParseError> {
         T::from_value(binding.get(self.index).unwrap())
     }
+
+    /// Like [Variable::extract], but returns `None` for unbound variables,
+    /// see [Constraint::optional].
+    pub fn extract_optional(self, binding: &Binding) -> Result<Option<T>, crate::ValueParseError> {
+        binding.get(self.index).map(T::from_value).transpose()
+    }
 }
 
 pub trait ContainsConstraint<'a, T> {
@@ -119,15 +137,22 @@ impl<T> Variable<T> {
     }
 }
 
-#[derive(Clone, Debug)]
+/// The values assigned to the variables of a query.
+///
+/// Storage grows with the largest variable that was bound,
+/// so cloning a binding is proportional to the size of the query.
+#[derive(Clone, Debug, Default)]
 pub struct Binding {
     pub bound: VariableSet,
-    values: [Value; 256],
+    values: Vec<Value>,
 }
 
 impl Binding {
     pub fn set(&mut self, variable: VariableId, value: Value) {
-        self.values[variable as usize] = value;
+        if variable >= self.values.len() {
+            self.values.resize(variable + 1, [0; 32]);
+        }
+        self.values[variable] = value;
         self.bound.set(variable);
     }
 
@@ -137,33 +162,97 @@ impl Binding {
 
     pub fn get(&self, variable: VariableId) -> Option<Value> {
         if self.bound.is_set(variable) {
-            Some(self.values[variable as usize])
+            Some(self.values[variable])
         } else {
             None
         }
     }
 }
 
-impl Default for Binding {
-    fn default() -> Self {
-        Self {
-            bound: ByteBitset::new_empty(),
-            values: [[0; 32]; 256],
-        }
-    }
-}
-
 pub trait Constraint<'a> {
     fn variables(&self) -> VariableSet;
     fn variable(&self, variable: VariableId) -> bool;
     fn estimate(&self, variable: VariableId, binding: &Binding) -> usize;
     fn propose(&self, variable: VariableId, binding: &Binding) -> Vec<Value>;
     fn confirm(&self, variable: VariableId, binding: &Binding, proposal: &mut Vec<Value>);
+
+    /// Returns the exact number of values that `propose` would return,
+    /// if the constraint can determine it without proposing them.
+    fn count(&self, _variable: VariableId, _binding: &Binding) -> Option<usize> {
+        None
+    }
+
+    /// Returns the variables that may stay unbound in a solution,
+    /// because the constraint has no values for them, see [OptionalConstraint].
+    fn optional(&self) -> VariableSet {
+        VariableSet::new_empty()
+    }
+}
+
+/// Checks if the constraint has any solution where the variables in `fixed`
+/// take on their given values, by searching over the remaining unbound variables.
+///
+/// The variables in `fixed` must be unbound in the passed binding.
+pub(crate) fn satisfiable<'a, C>(
+    constraint: &C,
+    binding: &mut Binding,
+    fixed: &[(VariableId, Value)],
+) -> bool
+where
+    C: Constraint<'a> + ?Sized,
+{
+    if let Some((&(variable, value), rest)) = fixed.split_first() {
+        let mut proposal = vec![value];
+        constraint.confirm(variable, binding, &mut proposal);
+        if proposal.is_empty() {
+            return false;
+        }
+        binding.set(variable, value);
+        let satisfied = satisfiable(constraint, binding, rest);
+        binding.unset(variable);
+        return satisfied;
+    }
+
+    let unbound = constraint.variables().subtract(&binding.bound);
+    let variable = match unbound
+        .into_iter()
+        .min_by_key(|&v| constraint.estimate(v, binding))
+    {
+        Some(variable) => variable,
+        None => return true,
+    };
+
+    for value in constraint.propose(variable, binding) {
+        binding.set(variable, value);
+        if satisfiable(constraint, binding, &[]) {
+            binding.unset(variable);
+            return true;
+        }
+    }
+    binding.unset(variable);
+    false
+}
+
+/// Unbinds the given variables, returning the resulting binding
+/// together with their previous values, for use with [satisfiable].
+pub(crate) fn unbind(variables: VariableSet, binding: &Binding) -> (Binding, Vec<(VariableId, Value)>) {
+    let mut unbound = binding.clone();
+    let mut fixed = Vec::with_capacity(variables.count());
+    for variable in variables {
+        if let Some(value) = binding.get(variable) {
+            fixed.push((variable, value));
+            unbound.unset(variable);
+        }
+    }
+    (unbound, fixed)
 }
 
 pub struct State {
     variable: VariableId,
     values: Vec<Value>,
+    /// Set for optional variables without any proposed values,
+    /// which are then left unbound once.
+    absent: bool,
 }
 pub struct Query<C, P: Fn(&Binding) -> Result<R, ValueParseError>, R> {
     constraint: C,
@@ -172,11 +261,22 @@ pub struct Query<C, P: Fn(&Binding) -> Result<R, ValueParseError>, R> {
     binding: Binding,
     stack: Vec<State>,
     unbound: Vec<VariableId>,
+    optional: VariableSet,
 }
 
 impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> Query<C, P, R> {
+    /// Creates a query where the [Constraint::optional] variables stay unbound
+    /// whenever the constraint proposes no values for them,
+    /// instead of discarding the partial result.
     pub fn new(constraint: C, postprocessing: P) -> Self {
+        Self::with_required(constraint, VariableSet::new_empty(), postprocessing)
+    }
+
+    /// Like [Query::new], but discards the results where any of the
+    /// `required` variables would stay unbound.
+    pub fn with_required(constraint: C, required: VariableSet, postprocessing: P) -> Self {
         let variables = constraint.variables();
+        let optional = constraint.optional().subtract(&required);
         Query {
             constraint,
             postprocessing,
@@ -184,6 +284,7 @@ impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> Qu
             binding: Default::default(),
             stack: Vec::new(),
             unbound: Vec::from_iter(variables),
+            optional,
         }
     }
 }
@@ -204,22 +305,32 @@ impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> It
 
     // next() is the only required method
     fn next(&mut self) -> Option<Self::Item> {
+        if self.advance(false) {
+            Some((self.postprocessing)(&self.binding))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> Query<C, P, R> {
+    /// Runs the search until all variables are bound, or until a single
+    /// variable is left unbound if `skip_last` is set.
+    /// Returns false once the search is exhausted.
+    fn advance(&mut self, skip_last: bool) -> bool {
         loop {
             match &self.mode {
                 Search::Vertical => {
                     self.mode = Search::Horizontal;
 
-                    match self.unbound.len() {
+                    let next_variable = match self.unbound.len() {
                         0 => {
-                            return Some((self.postprocessing)(&self.binding));
+                            return true;
                         }
-                        1 => {
-                            let next_variable = self.unbound.pop().unwrap();
-                            self.stack.push(State {
-                                variable: next_variable,
-                                values: self.constraint.propose(next_variable, &self.binding),
-                            })
+                        1 if skip_last => {
+                            return true;
                         }
+                        1 => self.unbound.pop().unwrap(),
                         _ => {
                             let (index, &next_variable) = self
                                 .unbound
@@ -228,24 +339,30 @@ impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> It
                                 .min_by_key(|(_, &v)| self.constraint.estimate(v, &self.binding))
                                 .unwrap();
                             self.unbound.swap_remove(index);
-                            self.stack.push(State {
-                                variable: next_variable,
-                                values: self.constraint.propose(next_variable, &self.binding),
-                            });
+                            next_variable
                         }
-                    }
+                    };
+                    let values = self.constraint.propose(next_variable, &self.binding);
+                    self.stack.push(State {
+                        variable: next_variable,
+                        absent: values.is_empty() && self.optional.is_set(next_variable),
+                        values,
+                    });
                 }
                 Search::Horizontal => {
                     if let Some(state) = self.stack.last_mut() {
                         if let Some(assignment) = state.values.pop() {
                             self.binding.set(state.variable, assignment);
                             self.mode = Search::Vertical;
+                        } else if state.absent {
+                            state.absent = false;
+                            self.mode = Search::Vertical;
                         } else {
                             self.mode = Search::Backtrack;
                         }
                     } else {
                         self.mode = Search::Done;
-                        return None;
+                        return false;
                     }
                 }
                 Search::Backtrack => {
@@ -255,15 +372,105 @@ impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> It
                         self.mode = Search::Horizontal;
                     } else {
                         self.mode = Search::Done;
-                        return None;
+                        return false;
                     }
                 }
                 Search::Done => {
-                    return None;
+                    return false;
                 }
             }
         }
     }
+
+    /// Counts the remaining results.
+    ///
+    /// This is equivalent to [Iterator::count], but the values of the last
+    /// variable are counted via [Constraint::count] where possible,
+    /// instead of binding and postprocessing each of them.
+    pub fn count_solutions(mut self) -> usize {
+        let mut count = 0;
+        while self.advance(true) {
+            count += match self.unbound.last() {
+                None => 1,
+                Some(&variable) => self.count_last(variable),
+            }
+        }
+        count
+    }
+
+    /// Counts the results for each value of `key`,
+    /// without binding the last variable unless it is `key` itself.
+    /// Results where `key` stays unbound are not counted.
+    ///
+    /// Only the distinct values of `key` are parsed, so this is cheaper
+    /// than [Aggregate::group_count] for queries built with [Query::new],
+    /// where the variables are at hand.
+    pub fn count_by<T>(mut self, key: Variable<T>) -> Result<HashMap<T, usize>, ValueParseError>
+    where
+        T: Valuelike + Eq + Hash,
+    {
+        let mut counts: HashMap<Value, usize> = HashMap::new();
+        while self.advance(true) {
+            match self.unbound.last() {
+                Some(&variable) if variable == key.index => {
+                    for value in self.constraint.propose(variable, &self.binding) {
+                        *counts.entry(value).or_insert(0) += 1;
+                    }
+                }
+                last => {
+                    let count = last.map_or(1, |&variable| self.count_last(variable));
+                    if let (Some(value), true) = (self.binding.get(key.index), count > 0) {
+                        *counts.entry(value).or_insert(0) += count;
+                    }
+                }
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(value, count)| Ok((T::from_value(value)?, count)))
+            .collect()
+    }
+
+    /// Returns the distinct values that `variable` takes on in the results,
+    /// without binding the last variable unless it is `variable` itself.
+    ///
+    /// Only the distinct values are parsed, so this is cheaper than
+    /// [Aggregate::count_distinct], [Aggregate::min_of] or [Aggregate::max_of]
+    /// over a single variable, for queries built with [Query::new].
+    pub fn distinct<T>(mut self, variable: Variable<T>) -> Result<Vec<T>, ValueParseError>
+    where
+        T: Valuelike,
+    {
+        let mut values = HashSet::new();
+        while self.advance(true) {
+            match self.unbound.last() {
+                Some(&last) if last == variable.index => {
+                    values.extend(self.constraint.propose(last, &self.binding));
+                }
+                last => {
+                    let count = last.map_or(1, |&last| self.count_last(last));
+                    if let (Some(value), true) = (self.binding.get(variable.index), count > 0) {
+                        values.insert(value);
+                    }
+                }
+            }
+        }
+        values.into_iter().map(T::from_value).collect()
+    }
+
+    /// The number of results for the values of the last unbound variable,
+    /// which is one if it is optional and has no values.
+    fn count_last(&self, variable: VariableId) -> usize {
+        let values = self
+            .constraint
+            .count(variable, &self.binding)
+            .unwrap_or_else(|| self.constraint.propose(variable, &self.binding).len());
+        if values == 0 && self.optional.is_set(variable) {
+            1
+        } else {
+            values
+        }
+    }
 }
 
 impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> fmt::Debug
@@ -274,15 +481,50 @@ impl<'a, C: Constraint<'a>, P: Fn(&Binding) -> Result<R, ValueParseError>, R> fm
     }
 }
 
+/// Creates a [Query] that finds all bindings of the listed variables
+/// which satisfy the constraint.
+///
+/// Variables that the constraint marks as [Constraint::optional],
+/// e.g. with `?` in a namespace `pattern!`, are returned as an [Option]
+/// when they are also prefixed with `?` here, e.g. `find!(ctx, (person, ?nick), ...)`.
+/// The result is `None` whenever the constraint has no value for them.
+/// Without the prefix, the results where they have no value are skipped.
 #[macro_export]
 macro_rules! find {
-    ($ctx:ident, ($($Var:ident),+), $Constraint:expr) => {
+    (@declare $ctx:ident, $required:ident) => {};
+    (@declare $ctx:ident, $required:ident, $($rest:tt)*) => {
+        $crate::find!(@declare $ctx, $required $($rest)*);
+    };
+    (@declare $ctx:ident, $required:ident ? $Var:ident $($rest:tt)*) => {
+        let $Var = $ctx.next_variable();
+        $crate::find!(@declare $ctx, $required $($rest)*);
+    };
+    (@declare $ctx:ident, $required:ident $Var:ident $($rest:tt)*) => {
+        let $Var = $ctx.next_variable();
+        $required.set($Var.index);
+        $crate::find!(@declare $ctx, $required $($rest)*);
+    };
+    (@extract $binding:ident, ($($Extracted:tt)*)) => {
+        ($($Extracted)*)
+    };
+    (@extract $binding:ident, ($($Extracted:tt)*) , $($rest:tt)*) => {
+        $crate::find!(@extract $binding, ($($Extracted)*) $($rest)*)
+    };
+    (@extract $binding:ident, ($($Extracted:tt)*) ? $Var:ident $($rest:tt)*) => {
+        $crate::find!(@extract $binding, ($($Extracted)* $Var.extract_optional($binding)?,) $($rest)*)
+    };
+    (@extract $binding:ident, ($($Extracted:tt)*) $Var:ident $($rest:tt)*) => {
+        $crate::find!(@extract $binding, ($($Extracted)* $Var.extract($binding)?,) $($rest)*)
+    };
+    ($ctx:ident, ($($Vars:tt)+), $Constraint:expr) => {
         {
             let mut $ctx = $crate::query::VariableContext::new();
-            $(let $Var = $ctx.next_variable();)*
-              $crate::query::Query::new($Constraint,
+            #[allow(unused_mut)]
+            let mut required = $crate::query::VariableSet::new_empty();
+            $crate::find!(@declare $ctx, required $($Vars)+);
+              $crate::query::Query::with_required($Constraint, required,
                 move |binding| {
-                    Ok(($($Var.extract(binding)?),+,))
+                    Ok($crate::find!(@extract binding, () $($Vars)+))
             })
         }
     };
@@ -353,6 +595,83 @@ mod tests {
         */
     }
 
+    #[test]
+    fn not_set() {
+        let mut books = HashSet::new();
+        let mut movies = HashSet::new();
+
+        books.insert(ShortString::new("LOTR").unwrap());
+        books.insert(ShortString::new("Dragonrider").unwrap());
+        books.insert(ShortString::new("Highlander").unwrap());
+
+        movies.insert(ShortString::new("LOTR").unwrap());
+        movies.insert(ShortString::new("Highlander").unwrap());
+
+        let only_books: Vec<_> =
+            find!(ctx, (a), and!(books.has(a), not!(ctx, movies.has(a)))).collect();
+
+        assert_eq!(
+            vec![Ok((ShortString::new("Dragonrider").unwrap(),))],
+            only_books
+        );
+
+        // A negation can't propose values for a variable nothing else binds.
+        let unbound: Vec<_> = find!(
+            ctx,
+            (a, b),
+            and!(books.has(a), not!(ctx, movies.has(b)))
+        )
+        .collect();
+
+        assert!(unbound.is_empty());
+    }
+
+    #[test]
+    fn or_set() {
+        let mut books = HashSet::new();
+        let mut movies = HashSet::new();
+
+        books.insert(ShortString::new("LOTR").unwrap());
+        books.insert(ShortString::new("Dragonrider").unwrap());
+        books.insert(ShortString::new("Highlander").unwrap());
+
+        movies.insert(ShortString::new("LOTR").unwrap());
+        movies.insert(ShortString::new("Highlander").unwrap());
+
+        let either: Vec<_> =
+            find!(ctx, (a), or!(ctx, books.has(a), movies.has(a)).unwrap()).collect();
+
+        assert_eq!(either.len(), 3);
+
+        let disjoint: Vec<_> = find!(
+            ctx,
+            (a),
+            or!(
+                ctx,
+                and!(books.has(a), not!(ctx, movies.has(a))),
+                and!(movies.has(a), not!(ctx, books.has(a)))
+            )
+            .unwrap()
+        )
+        .collect();
+
+        assert_eq!(
+            vec![Ok((ShortString::new("Dragonrider").unwrap(),))],
+            disjoint
+        );
+
+        let mut ctx = VariableContext::new();
+        let a = ctx.next_variable::<ShortString>();
+        let b = ctx.next_variable::<ShortString>();
+        assert_eq!(
+            or!(ctx, books.has(a), movies.has(b)).err(),
+            Some(UnconstrainedVariable {
+                alternative: 0,
+                variable: b.index
+            })
+        );
+    }
+
     #[test]
     fn pattern() {
         let romeo = ufoid();
@@ -389,4 +708,134 @@ mod tests {
 
         assert_eq!(1, r.len())
     }
+
+    #[test]
+    fn aggregate() {
+        let romeo = ufoid();
+        let juliet = ufoid();
+        let paris = ufoid();
+        let mut kb = TribleSet::new();
+
+        kb.union(knights::entity!(juliet, {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet
+        }));
+        kb.union(knights::entity!(paris, {
+            name: "Paris".try_into().unwrap(),
+            loves: juliet
+        }));
+        kb.union(knights::entity!({
+            name: "Romeo".try_into().unwrap()
+        }));
+
+        let count =
+            find!(ctx, (e, name), knights::pattern!(ctx, kb, [{e @ name: name}])).count_solutions();
+        assert_eq!(4, count);
+
+        let lovers = find!(
+            ctx,
+            (lover, beloved),
+            knights::pattern!(ctx, kb, [{lover @ loves: beloved}])
+        )
+        .count_solutions();
+        assert_eq!(3, lovers);
+
+        let names = find!(ctx, (e, name), knights::pattern!(ctx, kb, [{e @ name: name}]))
+            .map(|r| r.map(|(_, name)| name))
+            .count_distinct()
+            .unwrap();
+        assert_eq!(3, names);
+
+        let first = find!(ctx, (e, name), knights::pattern!(ctx, kb, [{e @ name: name}]))
+            .min_of(|(_, name)| name)
+            .unwrap();
+        assert_eq!(Some("Juliet".try_into().unwrap()), first);
+
+        let last = find!(ctx, (e, name), knights::pattern!(ctx, kb, [{e @ name: name}]))
+            .max_of(|(_, name)| name)
+            .unwrap();
+        assert_eq!(Some("Romeo".try_into().unwrap()), last);
+
+        let admirers = find!(
+            ctx,
+            (lover, beloved, name),
+            knights::pattern!(ctx, kb, [{lover @ loves: beloved}, {beloved @ name: name}])
+        )
+        .group_count(|(_, _, name)| String::from(&name))
+        .unwrap();
+        assert_eq!(Some(&2), admirers.get("Juliet"));
+        assert_eq!(Some(&1), admirers.get("Romeo"));
+
+        let suitors = find!(
+            ctx,
+            (lover, beloved),
+            knights::pattern!(ctx, kb, [{lover @ loves: beloved}])
+        )
+        .group_by(|(lover, beloved)| (beloved, lover))
+        .unwrap();
+        assert_eq!(2, suitors[&juliet].len());
+    }
+
+    #[test]
+    fn aggregate_variables() {
+        let romeo = ufoid();
+        let juliet = ufoid();
+        let paris = ufoid();
+        let mut kb = TribleSet::new();
+
+        kb.union(knights::entity!(juliet, {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet
+        }));
+        kb.union(knights::entity!(paris, {
+            name: "Paris".try_into().unwrap(),
+            loves: juliet
+        }));
+
+        let mut ctx = VariableContext::new();
+        let lover = ctx.next_variable::<Id>();
+        let beloved = ctx.next_variable::<Id>();
+        let name = ctx.next_variable::<ShortString>();
+
+        let pattern =
+            knights::pattern!(ctx, kb, [{lover @ loves: beloved}, {beloved @ name: name}]);
+        let admirers = Query::new(pattern, |_| Ok(())).count_by(name).unwrap();
+        assert_eq!(Some(&2), admirers.get(&"Juliet".try_into().unwrap()));
+        assert_eq!(Some(&1), admirers.get(&"Romeo".try_into().unwrap()));
+        assert_eq!(2, admirers.len());
+
+        let pattern =
+            knights::pattern!(ctx, kb, [{lover @ loves: beloved}, {beloved @ name: name}]);
+        let mut beloveds = Query::new(pattern, |_| Ok(())).distinct(beloved).unwrap();
+        beloveds.sort();
+        let mut expected = vec![romeo, juliet];
+        expected.sort();
+        assert_eq!(expected, beloveds);
+    }
+
+    #[test]
+    fn many_variables() {
+        let mut ctx = VariableContext::new();
+        let constraints: Vec<Box<dyn Constraint>> = (0..300)
+            .map(|i| {
+                let v: Variable<ShortString> = ctx.next_variable();
+                Box::new(v.is(ShortString::new(i.to_string()).unwrap())) as Box<dyn Constraint>
+            })
+            .collect();
+        let last: Variable<ShortString> = Variable::new(299);
+
+        let r: Vec<_> = Query::new(IntersectionConstraint::new(constraints), move |binding| {
+            last.extract(binding)
+        })
+        .collect();
+        assert_eq!(vec![Ok(ShortString::new("299").unwrap())], r);
+    }
 }
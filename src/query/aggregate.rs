@@ -0,0 +1,88 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::ValueParseError;
+
+/// Aggregations over query results, e.g. the ones returned by [crate::query::find].
+///
+/// These work on any iterator of results, so every result is bound and
+/// parsed before it is aggregated, and they stop at the first one that
+/// failed to parse. Plain counts are better done with
+/// [crate::query::Query::count_solutions], and aggregations over a single
+/// variable with [crate::query::Query::count_by] or [crate::query::Query::distinct],
+/// which leave the last variable unbound and only parse distinct values.
+pub trait Aggregate<R>: Iterator<Item = Result<R, ValueParseError>> + Sized {
+    /// Counts the distinct results.
+    fn count_distinct(self) -> Result<usize, ValueParseError>
+    where
+        R: Eq + Hash,
+    {
+        let mut distinct = HashSet::new();
+        for result in self {
+            distinct.insert(result?);
+        }
+        Ok(distinct.len())
+    }
+
+    /// Returns the smallest of the values extracted from the results.
+    fn min_of<T, F>(self, mut f: F) -> Result<Option<T>, ValueParseError>
+    where
+        T: Ord,
+        F: FnMut(R) -> T,
+    {
+        let mut min = None;
+        for result in self {
+            let value = f(result?);
+            if min.as_ref().map_or(true, |min| value < *min) {
+                min = Some(value);
+            }
+        }
+        Ok(min)
+    }
+
+    /// Returns the largest of the values extracted from the results.
+    fn max_of<T, F>(self, mut f: F) -> Result<Option<T>, ValueParseError>
+    where
+        T: Ord,
+        F: FnMut(R) -> T,
+    {
+        let mut max = None;
+        for result in self {
+            let value = f(result?);
+            if max.as_ref().map_or(true, |max| value > *max) {
+                max = Some(value);
+            }
+        }
+        Ok(max)
+    }
+
+    /// Splits every result into a key and a value,
+    /// and collects the values of each key.
+    fn group_by<K, V, F>(self, mut f: F) -> Result<HashMap<K, Vec<V>>, ValueParseError>
+    where
+        K: Eq + Hash,
+        F: FnMut(R) -> (K, V),
+    {
+        let mut groups: HashMap<K, Vec<V>> = HashMap::new();
+        for result in self {
+            let (key, value) = f(result?);
+            groups.entry(key).or_default().push(value);
+        }
+        Ok(groups)
+    }
+
+    /// Counts the results for each key.
+    fn group_count<K, F>(self, mut f: F) -> Result<HashMap<K, usize>, ValueParseError>
+    where
+        K: Eq + Hash,
+        F: FnMut(R) -> K,
+    {
+        let mut counts = HashMap::new();
+        for result in self {
+            *counts.entry(f(result?)).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+}
+
+impl<R, I> Aggregate<R> for I where I: Iterator<Item = Result<R, ValueParseError>> {}
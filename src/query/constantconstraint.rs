@@ -37,4 +37,8 @@ impl<'a, T> Constraint<'a> for ConstantConstraint<T> {
     fn confirm(&self, _variable: VariableId, _binding: &Binding, proposals: &mut Vec<Value>) {
         proposals.retain(|v| *v == self.constant);
     }
+
+    fn count(&self, _variable: VariableId, _binding: &Binding) -> Option<usize> {
+        Some(1)
+    }
 }
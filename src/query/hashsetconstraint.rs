@@ -42,6 +42,10 @@ where
     fn confirm(&self, _variable: VariableId, _binding: &Binding, proposals: &mut Vec<Value>) {
         proposals.retain(|v| T::from_value(*v).map_or(false, |v| self.set.contains(&v)));
     }
+
+    fn count(&self, _variable: VariableId, _binding: &Binding) -> Option<usize> {
+        Some(self.set.len())
+    }
 }
 
 impl<'a, T> ContainsConstraint<'a, T> for HashSet<T>
@@ -14,7 +14,7 @@ impl<'a> Constraint<'a> for IntersectionConstraint<'a> {
     fn variables(&self) -> VariableSet {
         self.constraints
             .iter()
-            .fold(VariableSet::new_empty(), |vs, c| vs.union(c.variables()))
+            .fold(VariableSet::new_empty(), |vs, c| vs.union(&c.variables()))
     }
 
     fn variable(&self, variable: VariableId) -> bool {
@@ -59,6 +59,27 @@ impl<'a> Constraint<'a> for IntersectionConstraint<'a> {
             .iter()
             .for_each(|c| c.confirm(variable, binding, proposals));
     }
+
+    fn count(&self, variable: VariableId, binding: &Binding) -> Option<usize> {
+        let mut relevant_constraints = self.constraints.iter().filter(|c| c.variable(variable));
+        match (relevant_constraints.next(), relevant_constraints.next()) {
+            (Some(c), None) => c.count(variable, binding),
+            _ => None,
+        }
+    }
+
+    /// A variable is only optional if no constraint requires it.
+    fn optional(&self) -> VariableSet {
+        let (optional, required) = self.constraints.iter().fold(
+            (VariableSet::new_empty(), VariableSet::new_empty()),
+            |(optional, required), c| {
+                let o = c.optional();
+                let r = c.variables().subtract(&o);
+                (optional.union(&o), required.union(&r))
+            },
+        );
+        optional.subtract(&required)
+    }
 }
 
 #[macro_export]
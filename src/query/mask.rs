@@ -13,7 +13,7 @@ impl<'a> MaskConstraint<'a> {
 
 impl<'a> Constraint<'a> for MaskConstraint<'a> {
     fn variables(&self) -> VariableSet {
-        self.constraint.variables().intersect(self.mask)
+        self.constraint.variables().intersect(&self.mask)
     }
 
     fn variable(&self, variable: VariableId) -> bool {
@@ -31,6 +31,10 @@ impl<'a> Constraint<'a> for MaskConstraint<'a> {
     fn confirm(&self, variable: VariableId, binding: &Binding, proposals: &mut Vec<Value>) {
         self.constraint.confirm(variable, binding, proposals)
     }
+
+    fn optional(&self) -> VariableSet {
+        self.constraint.optional().intersect(&self.mask)
+    }
 }
 
 #[macro_export]
@@ -0,0 +1,90 @@
+use super::*;
+
+/// Negates the wrapped constraint.
+///
+/// The variables of the negated constraint are split into those that
+/// are shared with the rest of the query and those that are local to the
+/// negation. Shared variables must be bound by some other (positive) constraint,
+/// as a negation can only remove candidates but never propose them,
+/// otherwise the query has no results.
+/// Local variables are existentially quantified, so a binding is rejected
+/// if there is any assignment of them that satisfies the negated constraint.
+pub struct NotConstraint<'a> {
+    local: VariableSet,
+    constraint: Box<dyn Constraint<'a> + 'a>,
+}
+
+impl<'a> NotConstraint<'a> {
+    pub fn new(local: VariableSet, constraint: Box<dyn Constraint<'a> + 'a>) -> Self {
+        NotConstraint { local, constraint }
+    }
+}
+
+impl<'a> Constraint<'a> for NotConstraint<'a> {
+    fn variables(&self) -> VariableSet {
+        self.constraint.variables().subtract(&self.local)
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        !self.local.is_set(variable) && self.constraint.variable(variable)
+    }
+
+    fn estimate(&self, _variable: VariableId, _binding: &Binding) -> usize {
+        usize::MAX
+    }
+
+    /// A negation has no values to propose, so a shared variable
+    /// that no positive constraint binds has no solutions.
+    fn propose(&self, _variable: VariableId, _binding: &Binding) -> Vec<Value> {
+        Vec::new()
+    }
+
+    fn confirm(&self, variable: VariableId, binding: &Binding, proposals: &mut Vec<Value>) {
+        let mut unbound = self.variables().subtract(&binding.bound);
+        unbound.unset(variable);
+        if !unbound.is_empty() {
+            // We can only decide once all shared variables are known.
+            return;
+        }
+
+        let (mut binding, mut fixed) = unbind(self.variables(), binding);
+        fixed.push((variable, [0; 32]));
+        proposals.retain(|&value| {
+            fixed.last_mut().unwrap().1 = value;
+            !satisfiable(self.constraint.as_ref(), &mut binding, &fixed)
+        });
+    }
+}
+
+/// Creates a [NotConstraint] from the given constraint.
+///
+/// Variables created from `$ctx` while constructing the constraint,
+/// e.g. the ones listed as `(x, y)`, are local to the negation.
+#[macro_export]
+macro_rules! not {
+    ($ctx:ident, ($($Var:ident),+), $c:expr) => (
+        {
+            let start = $ctx.next_index;
+            $(let $Var = $ctx.next_variable();)*
+            let constraint = $c;
+            let mut local = $crate::query::VariableSet::new_empty();
+            for index in start..$ctx.next_index {
+                local.set(index);
+            }
+            $crate::query::notconstraint::NotConstraint::new(local, Box::new(constraint))
+        }
+    );
+    ($ctx:ident, $c:expr) => (
+        {
+            let start = $ctx.next_index;
+            let constraint = $c;
+            let mut local = $crate::query::VariableSet::new_empty();
+            for index in start..$ctx.next_index {
+                local.set(index);
+            }
+            $crate::query::notconstraint::NotConstraint::new(local, Box::new(constraint))
+        }
+    );
+}
+
+pub use not;
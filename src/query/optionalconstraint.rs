@@ -0,0 +1,85 @@
+use super::*;
+
+/// Makes the optional variables of the wrapped constraint optional.
+///
+/// The remaining variables of the wrapped constraint must be bound by some
+/// other constraint, as an optional constraint never removes their candidates.
+/// Once they are bound, the optional variables are proposed the values that
+/// satisfy the wrapped constraint, which is none if there is no match.
+/// They are reported by [Constraint::optional], so that a [Query] leaves
+/// them unbound instead of discarding the partial result.
+///
+/// Local variables are existentially quantified, like in [NotConstraint].
+pub struct OptionalConstraint<'a> {
+    local: VariableSet,
+    optional: VariableSet,
+    constraint: Box<dyn Constraint<'a> + 'a>,
+}
+
+impl<'a> OptionalConstraint<'a> {
+    pub fn new(
+        local: VariableSet,
+        optional: VariableSet,
+        constraint: Box<dyn Constraint<'a> + 'a>,
+    ) -> Self {
+        OptionalConstraint {
+            local,
+            optional,
+            constraint,
+        }
+    }
+
+    /// Returns true once all non optional variables are bound.
+    fn ready(&self, binding: &Binding) -> bool {
+        binding
+            .bound
+            .is_superset_of(&self.variables().subtract(&self.optional))
+    }
+}
+
+impl<'a> Constraint<'a> for OptionalConstraint<'a> {
+    fn variables(&self) -> VariableSet {
+        self.constraint.variables().subtract(&self.local)
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        !self.local.is_set(variable) && self.constraint.variable(variable)
+    }
+
+    fn estimate(&self, variable: VariableId, binding: &Binding) -> usize {
+        if self.optional.is_set(variable) && self.ready(binding) {
+            self.constraint.estimate(variable, binding)
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Only proposes optional variables once the others are bound,
+    /// which the [Constraint::estimate] of `usize::MAX` defers until then.
+    fn propose(&self, variable: VariableId, binding: &Binding) -> Vec<Value> {
+        if !self.optional.is_set(variable) || !self.ready(binding) {
+            return Vec::new();
+        }
+        let mut proposals = self.constraint.propose(variable, binding);
+        self.confirm(variable, binding, &mut proposals);
+        proposals
+    }
+
+    fn confirm(&self, variable: VariableId, binding: &Binding, proposals: &mut Vec<Value>) {
+        if !self.optional.is_set(variable) || !self.ready(binding) {
+            return;
+        }
+        // Unbound optional variables and the local ones are
+        // searched for, so that every value has a complete match.
+        let (mut binding, mut fixed) = unbind(self.variables(), binding);
+        fixed.push((variable, [0; 32]));
+        proposals.retain(|&value| {
+            fixed.last_mut().unwrap().1 = value;
+            satisfiable(self.constraint.as_ref(), &mut binding, &fixed)
+        });
+    }
+
+    fn optional(&self) -> VariableSet {
+        self.optional.clone().subtract(&self.local)
+    }
+}
@@ -48,6 +48,10 @@ where
     fn confirm(&self, _variable: VariableId, _binding: &Binding, proposals: &mut Vec<Value>) {
         proposals.retain(|v| self.patch.has_prefix(v));
     }
+
+    fn count(&self, _variable: VariableId, _binding: &Binding) -> Option<usize> {
+        Some(self.patch.len() as usize)
+    }
 }
 
 impl<'a, T> ContainsConstraint<'a, T> for PATCH<VALUE_LEN, IdentityOrder, SingleSegmentation>
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::error::Error;
+
+use super::*;
+
+/// Matches if any of the wrapped constraints match.
+///
+/// Every alternative must constrain all variables shared with the rest of
+/// the query, otherwise a shared variable could take on any value.
+/// Variables that are local to the union are existentially quantified
+/// within each alternative, so they never leak into the results.
+pub struct UnionConstraint<'a> {
+    local: VariableSet,
+    constraints: Vec<Box<dyn Constraint<'a> + 'a>>,
+}
+
+/// An alternative of a [UnionConstraint] doesn't constrain
+/// a variable that is shared with the rest of the query.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnconstrainedVariable {
+    /// The index of the alternative.
+    pub alternative: usize,
+    /// The shared variable it doesn't constrain.
+    pub variable: VariableId,
+}
+
+impl fmt::Display for UnconstrainedVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "alternative {} of the union doesn't constrain the shared variable {}",
+            self.alternative, self.variable
+        )
+    }
+}
+
+impl Error for UnconstrainedVariable {}
+
+impl<'a> UnionConstraint<'a> {
+    pub fn new(
+        local: VariableSet,
+        constraints: Vec<Box<dyn Constraint<'a> + 'a>>,
+    ) -> Result<Self, UnconstrainedVariable> {
+        let shared = constraints
+            .iter()
+            .fold(VariableSet::new_empty(), |vs, c| vs.union(&c.variables()))
+            .subtract(&local);
+        for (alternative, c) in constraints.iter().enumerate() {
+            if let Some(variable) = shared.clone().subtract(&c.variables()).into_iter().next() {
+                return Err(UnconstrainedVariable {
+                    alternative,
+                    variable,
+                });
+            }
+        }
+        Ok(UnionConstraint { local, constraints })
+    }
+
+    /// Returns true if `variable` is the last unbound shared variable,
+    /// in which case the alternatives have to be checked exactly.
+    fn is_last(&self, variable: VariableId, binding: &Binding) -> bool {
+        let mut unbound = self.variables().subtract(&binding.bound);
+        unbound.unset(variable);
+        unbound.is_empty()
+    }
+
+    fn satisfiable(
+        &self,
+        constraint: &dyn Constraint<'a>,
+        variable: VariableId,
+        binding: &Binding,
+        value: Value,
+    ) -> bool {
+        let (mut binding, mut fixed) = unbind(self.variables(), binding);
+        fixed.push((variable, value));
+        satisfiable(constraint, &mut binding, &fixed)
+    }
+}
+
+impl<'a> Constraint<'a> for UnionConstraint<'a> {
+    fn variables(&self) -> VariableSet {
+        self.constraints
+            .iter()
+            .fold(VariableSet::new_empty(), |vs, c| vs.union(&c.variables()))
+            .subtract(&self.local)
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        !self.local.is_set(variable) && self.constraints.iter().any(|c| c.variable(variable))
+    }
+
+    fn estimate(&self, variable: VariableId, binding: &Binding) -> usize {
+        // The union proposes at most the sum of its alternatives' proposals.
+        self.constraints
+            .iter()
+            .map(|c| c.estimate(variable, binding))
+            .fold(0, usize::saturating_add)
+    }
+
+    fn propose(&self, variable: VariableId, binding: &Binding) -> Vec<Value> {
+        let last = self.is_last(variable, binding);
+        let mut proposal: Vec<Value> = self
+            .constraints
+            .iter()
+            .flat_map(|c| {
+                let mut values = c.propose(variable, binding);
+                if last {
+                    values.retain(|&value| self.satisfiable(c.as_ref(), variable, binding, value));
+                }
+                values
+            })
+            .collect();
+        proposal.sort_unstable();
+        proposal.dedup();
+        proposal
+    }
+
+    fn confirm(&self, variable: VariableId, binding: &Binding, proposals: &mut Vec<Value>) {
+        if self.is_last(variable, binding) {
+            proposals.retain(|&value| {
+                self.constraints
+                    .iter()
+                    .any(|c| self.satisfiable(c.as_ref(), variable, binding, value))
+            });
+            return;
+        }
+
+        let mut confirmed = HashSet::new();
+        for c in &self.constraints {
+            let mut values = proposals.clone();
+            c.confirm(variable, binding, &mut values);
+            confirmed.extend(values);
+        }
+        proposals.retain(|value| confirmed.contains(value));
+    }
+
+    fn optional(&self) -> VariableSet {
+        self.constraints
+            .iter()
+            .fold(VariableSet::new_empty(), |vs, c| vs.union(&c.optional()))
+            .subtract(&self.local)
+    }
+}
+
+/// Creates a [UnionConstraint] from the given alternatives.
+///
+/// Variables created from `$ctx` while constructing the alternatives
+/// are local to the union. Returns an [UnconstrainedVariable] error
+/// if an alternative doesn't constrain all of the shared variables.
+#[macro_export]
+macro_rules! or {
+    ($ctx:ident, $($c:expr),+ $(,)?) => (
+        {
+            let start = $ctx.next_index;
+            let constraints: Vec<Box<dyn $crate::query::Constraint>> = vec![
+                $(Box::new($c)),+
+            ];
+            let mut local = $crate::query::VariableSet::new_empty();
+            for index in start..$ctx.next_index {
+                local.set(index);
+            }
+            $crate::query::unionconstraint::UnionConstraint::new(local, constraints)
+        }
+    );
+}
+
+pub use or;
@@ -0,0 +1,42 @@
+use std::ops::RangeInclusive;
+
+use super::*;
+
+/// A set of [Value]s that is covered by a few lexicographic byte ranges,
+/// which allows indices to propose its members without enumerating
+/// all of their values.
+pub trait ValueRange {
+    /// Returns inclusive lexicographic bounds that together cover
+    /// every value contained in the range.
+    fn bounds(&self) -> Vec<RangeInclusive<Value>>;
+
+    /// Checks if the value is a member of the range.
+    /// The bounds may be a superset of the contained values,
+    /// e.g. for ranges that also constrain later bytes of a value.
+    fn contains(&self, value: &Value) -> bool;
+}
+
+impl ValueRange for RangeInclusive<Value> {
+    fn bounds(&self) -> Vec<RangeInclusive<Value>> {
+        vec![self.clone()]
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        RangeInclusive::contains(self, value)
+    }
+}
+
+/// Implemented by datastructures that can efficiently propose the
+/// values of their tribles that lie within a [ValueRange].
+pub trait RangePattern {
+    type RangeConstraint<'a, V, R>: Constraint<'a>
+    where
+        V: Valuelike,
+        R: ValueRange + 'a,
+        Self: 'a;
+
+    fn value_range<'a, V, R>(&'a self, v: Variable<V>, range: R) -> Self::RangeConstraint<'a, V, R>
+    where
+        V: Valuelike,
+        R: ValueRange + 'a;
+}
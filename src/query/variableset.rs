@@ -0,0 +1,176 @@
+use std::fmt;
+use std::iter::FromIterator;
+
+use super::VariableId;
+
+/// A set of [VariableId]s that grows with the largest variable it contains.
+#[derive(Clone, Default)]
+pub struct VariableSet {
+    bits: Vec<u64>,
+}
+
+impl VariableSet {
+    /// Create a new empty set.
+    pub const fn new_empty() -> Self {
+        VariableSet { bits: Vec::new() }
+    }
+
+    /// Create a new set containing a single variable.
+    pub fn new_singleton(variable: VariableId) -> Self {
+        let mut set = Self::new_empty();
+        set.set(variable);
+        set
+    }
+
+    /// Check if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    /// Count the number of variables in the set.
+    pub fn count(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Add the given variable to the set.
+    pub fn set(&mut self, variable: VariableId) {
+        let word = variable / 64;
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        self.bits[word] |= 1 << (variable % 64);
+    }
+
+    /// Remove the given variable from the set.
+    pub fn unset(&mut self, variable: VariableId) {
+        if let Some(word) = self.bits.get_mut(variable / 64) {
+            *word &= !(1 << (variable % 64));
+        }
+    }
+
+    /// Check if the given variable is in the set.
+    pub fn is_set(&self, variable: VariableId) -> bool {
+        self.bits
+            .get(variable / 64)
+            .is_some_and(|word| word & (1 << (variable % 64)) != 0)
+    }
+
+    /// Check if every variable of `other` is also in this set.
+    pub fn is_superset_of(&self, other: &Self) -> bool {
+        other
+            .bits
+            .iter()
+            .enumerate()
+            .all(|(i, &word)| word & !self.bits.get(i).copied().unwrap_or(0) == 0)
+    }
+
+    /// Check if every variable of this set is also in `other`.
+    pub fn is_subset_of(&self, other: &Self) -> bool {
+        other.is_superset_of(self)
+    }
+
+    /// Keep only the variables that are also in `other`.
+    pub fn intersect(mut self, other: &Self) -> Self {
+        self.bits.truncate(other.bits.len());
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word &= other;
+        }
+        self
+    }
+
+    /// Add all variables of `other`.
+    pub fn union(mut self, other: &Self) -> Self {
+        if self.bits.len() < other.bits.len() {
+            self.bits.resize(other.bits.len(), 0);
+        }
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word |= other;
+        }
+        self
+    }
+
+    /// Remove all variables of `other`.
+    pub fn subtract(mut self, other: &Self) -> Self {
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word &= !other;
+        }
+        self
+    }
+
+    /// Iterate over the variables in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = VariableId> + '_ {
+        self.bits.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+impl PartialEq for VariableSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_superset_of(other) && other.is_superset_of(self)
+    }
+}
+
+impl Eq for VariableSet {}
+
+impl fmt::Debug for VariableSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl IntoIterator for VariableSet {
+    type Item = VariableId;
+    type IntoIter = std::vec::IntoIter<VariableId>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl FromIterator<VariableId> for VariableSet {
+    fn from_iter<I: IntoIterator<Item = VariableId>>(iter: I) -> Self {
+        let mut set = Self::new_empty();
+        for variable in iter {
+            set.set(variable);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn set_operations(left in prop::collection::vec(0usize..1000, 0..100),
+                          right in prop::collection::vec(0usize..1000, 0..100)) {
+            let l: VariableSet = left.iter().copied().collect();
+            let r: VariableSet = right.iter().copied().collect();
+
+            for &v in &left {
+                prop_assert!(l.is_set(v));
+            }
+            prop_assert!(l.clone().union(&r).is_superset_of(&l));
+            prop_assert!(l.clone().intersect(&r).is_subset_of(&r));
+            prop_assert!(l.clone().subtract(&r).intersect(&r).is_empty());
+            prop_assert_eq!(
+                l.clone().union(&r).count(),
+                l.count() + r.count() - l.clone().intersect(&r).count()
+            );
+            prop_assert_eq!(l.iter().collect::<Vec<_>>(), {
+                let mut sorted = left.clone();
+                sorted.sort();
+                sorted.dedup();
+                sorted
+            });
+        }
+    }
+}
@@ -1,6 +1,9 @@
+pub mod branch;
+pub mod filestore;
 pub mod head;
 pub mod objectstore;
 pub mod repo;
+pub mod workspace;
 
 pub use head::Head;
 pub use repo::Repo;
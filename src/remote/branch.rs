@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fmt::{self, Debug};
+
+use crate::{
+    namespace::NS,
+    query::find,
+    triblearchive::SimpleArchive,
+    types::{hash::Blake3, shortstring::FromStrError, Hash, ShortString},
+    BlobParseError, Bloblike, Id, TribleSet, ValueParseError,
+};
+
+use super::head::{CommitResult, Head};
+use super::repo::{Pull, Push};
+
+NS! {
+    pub namespace branch_ns {
+        "71F7438083BD07478E454F02BFC9C1B3" as name: ShortString;
+        "469BB76F0F325E69CBC1E15B3FB47E70" as commit: Hash<Blake3>;
+    }
+}
+
+/// A named pointer to a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub id: Id,
+    pub name: String,
+    /// The commit the branch points to, `None` for a branch without history.
+    pub commit: Option<Hash<Blake3>>,
+}
+
+#[derive(Debug)]
+pub enum BranchErr<PullErr, PushErr, CheckoutErr, CommitErr> {
+    Pull(PullErr),
+    Push(PushErr),
+    Checkout(CheckoutErr),
+    Commit(CommitErr),
+    Parse(BlobParseError),
+    BadValue(ValueParseError),
+    BadName(FromStrError),
+    Exists(String),
+    NotFound(String),
+}
+
+impl<PullErr, PushErr, CheckoutErr, CommitErr> fmt::Display
+    for BranchErr<PullErr, PushErr, CheckoutErr, CommitErr>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull(_) => write!(f, "failed to pull branch registry"),
+            Self::Push(_) => write!(f, "failed to push branch registry"),
+            Self::Checkout(_) => write!(f, "failed to checkout branch registry"),
+            Self::Commit(_) => write!(f, "failed to commit branch registry"),
+            Self::Parse(_) => write!(f, "malformed branch registry"),
+            Self::BadValue(_) => write!(f, "unexpected bad value in branch registry"),
+            Self::BadName(_) => write!(f, "branch names must be short strings"),
+            Self::Exists(name) => write!(f, "branch '{}' already exists", name),
+            Self::NotFound(name) => write!(f, "no branch named '{}'", name),
+        }
+    }
+}
+
+impl<PullErr, PushErr, CheckoutErr, CommitErr> Error
+    for BranchErr<PullErr, PushErr, CheckoutErr, CommitErr>
+where
+    PullErr: Debug + Error + 'static,
+    PushErr: Debug + Error + 'static,
+    CheckoutErr: Debug + Error + 'static,
+    CommitErr: Debug + Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Pull(e) => Some(e),
+            Self::Push(e) => Some(e),
+            Self::Checkout(e) => Some(e),
+            Self::Commit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type BranchResult<T, R, HD> = Result<
+    T,
+    BranchErr<
+        <R as Pull<Blake3>>::Err,
+        <R as Push<Blake3>>::Err,
+        <HD as Head<Blake3>>::CheckoutErr,
+        <HD as Head<Blake3>>::CommitErr,
+    >,
+>;
+
+/// Named branches, stored as tribles in a [SimpleArchive] blob of `repo`.
+///
+/// The `head` points to the current version of that archive, so every change
+/// to the branches, e.g. moving one of them, is a commit on the head.
+/// Changes are retried against the newest version when the head moved concurrently.
+pub struct Branches<R, HD> {
+    repo: R,
+    head: HD,
+}
+
+impl<R, HD> Branches<R, HD>
+where
+    R: Pull<Blake3> + Push<Blake3>,
+    HD: Head<Blake3>,
+{
+    pub fn new(repo: R, head: HD) -> Self {
+        Branches { repo, head }
+    }
+
+    /// Returns all branches, sorted by name.
+    pub async fn list(&self) -> BranchResult<Vec<Branch>, R, HD> {
+        let registry = self.head.checkout().await.map_err(BranchErr::Checkout)?;
+        self.load(registry).await
+    }
+
+    /// Returns the branch with the given name.
+    pub async fn get(&self, name: &str) -> BranchResult<Option<Branch>, R, HD> {
+        Ok(self.list().await?.into_iter().find(|b| b.name == name))
+    }
+
+    /// Creates a new branch without history.
+    pub async fn create(&self, name: &str) -> BranchResult<Branch, R, HD> {
+        self.insert(name, None).await
+    }
+
+    /// Creates a new branch that starts at `commit`.
+    pub async fn fork(&self, name: &str, commit: Hash<Blake3>) -> BranchResult<Branch, R, HD> {
+        self.insert(name, Some(commit)).await
+    }
+
+    /// Deletes the branch with the given name and returns it.
+    pub async fn delete(&self, name: &str) -> BranchResult<Branch, R, HD> {
+        let mut deleted = None;
+        self.update(|branches| {
+            let index = branches
+                .iter()
+                .position(|b| b.name == name)
+                .ok_or_else(|| BranchErr::NotFound(name.to_owned()))?;
+            deleted = Some(branches.remove(index));
+            Ok(true)
+        })
+        .await?;
+        Ok(deleted.unwrap())
+    }
+
+    /// Moves the branch with the given name from `old` to `new`.
+    ///
+    /// Like [Head::commit] this fails with a [CommitResult::Conflict]
+    /// containing the current commit, if the branch doesn't point to `old`.
+    /// Whether `new` descends from `old` isn't checked, any commit is accepted.
+    pub async fn compare_and_swap(
+        &self,
+        name: &str,
+        old: Option<Hash<Blake3>>,
+        new: Hash<Blake3>,
+    ) -> BranchResult<CommitResult<Blake3>, R, HD> {
+        let mut result = CommitResult::Success();
+        self.update(|branches| {
+            let branch = branches
+                .iter_mut()
+                .find(|b| b.name == name)
+                .ok_or_else(|| BranchErr::NotFound(name.to_owned()))?;
+            if branch.commit != old {
+                result = CommitResult::Conflict(branch.commit);
+                return Ok(false);
+            }
+            result = CommitResult::Success();
+            branch.commit = Some(new);
+            Ok(true)
+        })
+        .await?;
+        Ok(result)
+    }
+
+    /// Returns a [Head] that reads and moves the branch with the given name.
+    pub fn branch<'a>(&'a self, name: &str) -> BranchHead<'a, R, HD> {
+        BranchHead {
+            branches: self,
+            name: name.to_owned(),
+        }
+    }
+
+    async fn insert(
+        &self,
+        name: &str,
+        commit: Option<Hash<Blake3>>,
+    ) -> BranchResult<Branch, R, HD> {
+        ShortString::new(name).map_err(BranchErr::BadName)?;
+        let branch = Branch {
+            id: crate::id::ufoid(),
+            name: name.to_owned(),
+            commit,
+        };
+        self.update(|branches| {
+            if branches.iter().any(|b| b.name == name) {
+                return Err(BranchErr::Exists(name.to_owned()));
+            }
+            branches.push(branch.clone());
+            Ok(true)
+        })
+        .await?;
+        Ok(branch)
+    }
+
+    /// Applies `change` to the current branches and commits the result,
+    /// starting over with the new branches if the head moved in between.
+    /// `change` returns false if nothing needs to be committed.
+    async fn update<F>(&self, mut change: F) -> BranchResult<(), R, HD>
+    where
+        F: FnMut(&mut Vec<Branch>) -> BranchResult<bool, R, HD>,
+    {
+        loop {
+            let old = self.head.checkout().await.map_err(BranchErr::Checkout)?;
+            let mut branches = self.load(old).await?;
+            if !change(&mut branches)? {
+                return Ok(());
+            }
+            let new = self.store(&branches).await?;
+            match self
+                .head
+                .commit(old, new)
+                .await
+                .map_err(BranchErr::Commit)?
+            {
+                CommitResult::Success() => return Ok(()),
+                CommitResult::Conflict(_) => continue,
+            }
+        }
+    }
+
+    async fn load(&self, registry: Option<Hash<Blake3>>) -> BranchResult<Vec<Branch>, R, HD> {
+        let Some(registry) = registry else {
+            return Ok(Vec::new());
+        };
+        let blob = self.repo.pull(registry).await.map_err(BranchErr::Pull)?;
+        let archive = SimpleArchive::from_blob(blob).map_err(BranchErr::Parse)?;
+        let tribles = TribleSet::from(&archive);
+        let mut branches = find!(
+            ctx,
+            (id, name, ?commit),
+            branch_ns::pattern!(ctx, tribles, [
+            {id @
+                name: name,
+                ?commit: commit
+            }])
+        )
+        .map(|r| {
+            let (id, name, commit) = r.map_err(BranchErr::BadValue)?;
+            Ok(Branch {
+                id,
+                name: String::from(&name),
+                commit,
+            })
+        })
+        .collect::<BranchResult<Vec<Branch>, R, HD>>()?;
+        branches.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(branches)
+    }
+
+    async fn store(&self, branches: &[Branch]) -> BranchResult<Hash<Blake3>, R, HD> {
+        let mut tribles = TribleSet::new();
+        for branch in branches {
+            let name = ShortString::new(&branch.name).map_err(BranchErr::BadName)?;
+            tribles.union(branch_ns::entity!(branch.id, { name: name }));
+            if let Some(commit) = branch.commit {
+                tribles.union(branch_ns::entity!(branch.id, { commit: commit }));
+            }
+        }
+        let blob = SimpleArchive::from(&tribles).into_blob();
+        self.repo.push(blob).await.map_err(BranchErr::Push)
+    }
+}
+
+/// A single branch of [Branches], usable wherever a [Head] is expected.
+pub struct BranchHead<'a, R, HD> {
+    branches: &'a Branches<R, HD>,
+    name: String,
+}
+
+impl<'a, R, HD> Head<Blake3> for BranchHead<'a, R, HD>
+where
+    R: Pull<Blake3> + Push<Blake3>,
+    HD: Head<Blake3>,
+{
+    type CheckoutErr = BranchErr<
+        <R as Pull<Blake3>>::Err,
+        <R as Push<Blake3>>::Err,
+        <HD as Head<Blake3>>::CheckoutErr,
+        <HD as Head<Blake3>>::CommitErr,
+    >;
+    type CommitErr = Self::CheckoutErr;
+
+    async fn checkout(&self) -> Result<Option<Hash<Blake3>>, Self::CheckoutErr> {
+        match self.branches.get(&self.name).await? {
+            Some(branch) => Ok(branch.commit),
+            None => Err(BranchErr::NotFound(self.name.clone())),
+        }
+    }
+
+    async fn commit(
+        &self,
+        old: Option<Hash<Blake3>>,
+        new: Hash<Blake3>,
+    ) -> Result<CommitResult<Blake3>, Self::CommitErr> {
+        self.branches.compare_and_swap(&self.name, old, new).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlobSet;
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+
+    /// An in memory repo and head, good enough for a single thread.
+    struct MemoryRemote {
+        blobs: RefCell<BlobSet<Blake3>>,
+        head: RefCell<Option<Hash<Blake3>>>,
+    }
+
+    impl MemoryRemote {
+        fn new() -> Self {
+            MemoryRemote {
+                blobs: RefCell::new(BlobSet::new()),
+                head: RefCell::new(None),
+            }
+        }
+    }
+
+    impl Pull<Blake3> for &MemoryRemote {
+        type Err = crate::remote::repo::NotFoundErr;
+
+        async fn pull(&self, hash: Hash<Blake3>) -> Result<anybytes::Bytes, Self::Err> {
+            let blob = self.blobs.borrow().get_raw(hash).cloned();
+            blob.ok_or(crate::remote::repo::NotFoundErr())
+        }
+    }
+
+    impl Push<Blake3> for &MemoryRemote {
+        type Err = std::convert::Infallible;
+
+        async fn push(&self, blob: anybytes::Bytes) -> Result<Hash<Blake3>, Self::Err> {
+            Ok(self.blobs.borrow_mut().put_raw(blob))
+        }
+    }
+
+    impl Head<Blake3> for &MemoryRemote {
+        type CheckoutErr = std::convert::Infallible;
+        type CommitErr = std::convert::Infallible;
+
+        async fn checkout(&self) -> Result<Option<Hash<Blake3>>, Self::CheckoutErr> {
+            Ok(*self.head.borrow())
+        }
+
+        async fn commit(
+            &self,
+            old: Option<Hash<Blake3>>,
+            new: Hash<Blake3>,
+        ) -> Result<CommitResult<Blake3>, Self::CommitErr> {
+            let mut head = self.head.borrow_mut();
+            if *head != old {
+                return Ok(CommitResult::Conflict(*head));
+            }
+            *head = Some(new);
+            Ok(CommitResult::Success())
+        }
+    }
+
+    #[test]
+    fn branch_lifecycle() {
+        let remote = MemoryRemote::new();
+        let branches = Branches::new(&remote, &remote);
+        let a = Hash::new([1; 32]);
+        let b = Hash::new([2; 32]);
+
+        assert!(block_on(branches.list()).unwrap().is_empty());
+        block_on(branches.create("main")).unwrap();
+        block_on(branches.fork("feature", a)).unwrap();
+        assert!(matches!(
+            block_on(branches.create("main")),
+            Err(BranchErr::Exists(_))
+        ));
+
+        let listed: Vec<_> = block_on(branches.list())
+            .unwrap()
+            .into_iter()
+            .map(|b| (b.name, b.commit))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![("feature".to_owned(), Some(a)), ("main".to_owned(), None)]
+        );
+
+        assert!(matches!(
+            block_on(branches.compare_and_swap("main", Some(a), b)).unwrap(),
+            CommitResult::Conflict(None)
+        ));
+        let main = branches.branch("main");
+        assert!(matches!(
+            block_on(main.commit(None, a)).unwrap(),
+            CommitResult::Success()
+        ));
+        assert!(matches!(
+            block_on(main.commit(Some(a), b)).unwrap(),
+            CommitResult::Success()
+        ));
+        assert_eq!(block_on(main.checkout()).unwrap(), Some(b));
+
+        block_on(branches.delete("feature")).unwrap();
+        assert!(matches!(
+            block_on(branches.delete("feature")),
+            Err(BranchErr::NotFound(_))
+        ));
+        assert_eq!(block_on(branches.get("feature")).unwrap(), None);
+        assert_eq!(block_on(branches.list()).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,459 @@
+use std::array::TryFromSliceError;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use anybytes::Bytes;
+use blocking::unblock;
+use digest::{typenum::U32, Digest};
+use fd_lock::RwLock;
+use futures::{stream, Stream, StreamExt};
+
+use hex::FromHex;
+
+use crate::{types::Hash, Value};
+
+use super::head::{CommitResult, Head};
+use super::repo::{Contains, Forget, List, Modified, Pull, Push};
+
+const BLOB_DIR: &str = "blobs";
+const TMP_DIR: &str = "tmp";
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a file name that is unique among concurrent writers.
+fn tmp_name() -> String {
+    format!(
+        "{}-{}",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Returns `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Makes a rename into `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Writes `bytes` to `target` so that readers see either
+/// the previous file or the complete new one, never a partial write.
+///
+/// The data is first written and synced to `tmp`, which must be on the
+/// same filesystem as `target`, and then renamed over `target`.
+fn write_atomic(tmp: &Path, target: &Path, bytes: &[u8]) -> io::Result<()> {
+    let result = (|| {
+        let mut file = File::create(tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp, target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(tmp);
+    }
+    result?;
+    sync_dir(target.parent().unwrap_or(Path::new(".")))
+}
+
+/// A repository that stores every blob as a file named by the hex encoded
+/// hash of its content, inside the `blobs` directory of its root.
+///
+/// Blobs are written to the `tmp` directory first and renamed into place,
+/// so a crash never leaves a partial blob behind under its final name.
+/// Blobs are checked against their hash when they are pulled,
+/// which also catches files that were damaged after they were written.
+///
+/// The file system is accessed on the thread pool of the [blocking] crate,
+/// so the futures never block the executor that polls them.
+pub struct FileRepo<H> {
+    root: PathBuf,
+    _hasher: PhantomData<H>,
+}
+
+impl<H> FileRepo<H> {
+    /// Opens the repository at `root`, creating its directories if necessary.
+    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<FileRepo<H>> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(BLOB_DIR))?;
+        fs::create_dir_all(root.join(TMP_DIR))?;
+        Ok(FileRepo {
+            root,
+            _hasher: PhantomData,
+        })
+    }
+
+    fn blob_path(&self, hash: &Value) -> PathBuf {
+        self.root.join(BLOB_DIR).join(hex::encode(hash))
+    }
+}
+
+#[derive(Debug)]
+pub enum ListErr {
+    IO(io::Error),
+    BadNameHex(<Value as FromHex>::Error),
+}
+
+impl fmt::Display for ListErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "list failed: {}", e),
+            Self::BadNameHex(e) => write!(f, "list failed: {}", e),
+        }
+    }
+}
+
+impl Error for ListErr {}
+
+fn list_blobs(dir: &Path) -> Vec<Result<Value, ListErr>> {
+    match fs::read_dir(dir) {
+        Ok(dir) => dir
+            .map(|entry| {
+                let entry = entry.map_err(ListErr::IO)?;
+                let name = entry.file_name();
+                Value::from_hex(name.as_encoded_bytes()).map_err(ListErr::BadNameHex)
+            })
+            .collect(),
+        Err(e) => vec![Err(ListErr::IO(e))],
+    }
+}
+
+impl<H> List<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = ListErr;
+
+    fn list(&self) -> impl Stream<Item = Result<Hash<H>, Self::Err>> {
+        let dir = self.root.join(BLOB_DIR);
+        stream::once(unblock(move || list_blobs(&dir)))
+            .flat_map(stream::iter)
+            .map(|entry| entry.map(Hash::new))
+    }
+}
+
+#[derive(Debug)]
+pub enum PullErr {
+    IO(io::Error),
+    /// The stored content does not match its hash.
+    Corrupt,
+}
+
+impl fmt::Display for PullErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "pull failed: {}", e),
+            Self::Corrupt => write!(f, "pull failed: blob does not match its hash"),
+        }
+    }
+}
+
+impl Error for PullErr {}
+
+impl From<io::Error> for PullErr {
+    fn from(err: io::Error) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl<H> Pull<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = PullErr;
+
+    async fn pull(&self, hash: Hash<H>) -> Result<Bytes, Self::Err> {
+        let path = self.blob_path(&hash.bytes);
+        let content = unblock(move || fs::read(path)).await?;
+        let digest: Value = H::digest(&content).into();
+        if digest != hash.bytes {
+            return Err(PullErr::Corrupt);
+        }
+        Ok(content.into())
+    }
+}
+
+impl<H> Contains<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = io::Error;
+
+    async fn contains(&self, hash: Hash<H>) -> Result<bool, Self::Err> {
+        let path = self.blob_path(&hash.bytes);
+        unblock(move || path.try_exists()).await
+    }
+}
+
+impl<H> Push<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = io::Error;
+
+    async fn push(&self, blob: Bytes) -> Result<Hash<H>, Self::Err> {
+        let digest: Value = H::digest(&blob).into();
+        let path = self.blob_path(&digest);
+        let tmp = self.root.join(TMP_DIR).join(tmp_name());
+        unblock(move || {
+            if path.exists() {
+                // Keeps the blob within the grace period of garbage collection.
+                let file = OpenOptions::new().append(true).open(&path)?;
+                return file.set_modified(SystemTime::now());
+            }
+            write_atomic(&tmp, &path, &blob)
+        })
+        .await?;
+        Ok(Hash::new(digest))
+    }
+}
+
+impl<H> Modified<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = io::Error;
+
+    async fn modified(&self, hash: Hash<H>) -> Result<SystemTime, Self::Err> {
+        let path = self.blob_path(&hash.bytes);
+        unblock(move || fs::metadata(path)?.modified()).await
+    }
+}
+
+impl<H> Forget<H> for FileRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = io::Error;
+
+    async fn forget(&self, hash: Hash<H>) -> Result<(), Self::Err> {
+        let path = self.blob_path(&hash.bytes);
+        match unblock(move || fs::remove_file(path)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A [Head] stored in a single file that contains the raw hash.
+///
+/// Commits take an exclusive lock on a `.lock` file next to the head,
+/// so that the compare and the swap happen atomically with respect to
+/// other writers, and replace the head via a rename,
+/// so that readers never observe a torn hash.
+pub struct FileHead<H> {
+    path: PathBuf,
+    _hasher: PhantomData<H>,
+}
+
+impl<H> FileHead<H> {
+    /// Opens the head stored at `path`, creating its parent directories if necessary.
+    /// The head file itself is only created by the first commit.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileHead<H>> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(FileHead {
+            path,
+            _hasher: PhantomData,
+        })
+    }
+}
+
+fn read_head(path: &Path) -> Result<Option<Value>, CheckoutErr> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some((&bytes[..]).try_into()?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Replaces the head at `path` with `new` if it still is `old`,
+/// and otherwise returns the stored head.
+fn swap_head(
+    path: &Path,
+    old: Option<Value>,
+    new: Value,
+) -> Result<Option<Option<Value>>, CommitErr> {
+    let mut lock = RwLock::new(
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(path, ".lock"))?,
+    );
+    // The lock is released when the guard is dropped.
+    let _guard = lock.write()?;
+
+    let stored = read_head(path)?;
+    if stored != old {
+        return Ok(Some(stored));
+    }
+    write_atomic(&sibling(path, ".tmp"), path, &new)?;
+    Ok(None)
+}
+
+#[derive(Debug)]
+pub enum CheckoutErr {
+    ValidationErr(TryFromSliceError),
+    StoreErr(io::Error),
+}
+
+impl fmt::Display for CheckoutErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StoreErr(e) => write!(f, "checkout failed: {}", e),
+            Self::ValidationErr(e) => write!(f, "checkout failed: {}", e),
+        }
+    }
+}
+
+impl Error for CheckoutErr {}
+
+impl From<io::Error> for CheckoutErr {
+    fn from(err: io::Error) -> Self {
+        Self::StoreErr(err)
+    }
+}
+
+impl From<TryFromSliceError> for CheckoutErr {
+    fn from(err: TryFromSliceError) -> Self {
+        Self::ValidationErr(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum CommitErr {
+    ValidationErr(TryFromSliceError),
+    StoreErr(io::Error),
+}
+
+impl fmt::Display for CommitErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ValidationErr(e) => write!(f, "commit failed: {}", e),
+            Self::StoreErr(e) => write!(f, "commit failed: {}", e),
+        }
+    }
+}
+
+impl Error for CommitErr {}
+
+impl From<io::Error> for CommitErr {
+    fn from(err: io::Error) -> Self {
+        Self::StoreErr(err)
+    }
+}
+
+impl From<CheckoutErr> for CommitErr {
+    fn from(err: CheckoutErr) -> Self {
+        match err {
+            CheckoutErr::ValidationErr(e) => Self::ValidationErr(e),
+            CheckoutErr::StoreErr(e) => Self::StoreErr(e),
+        }
+    }
+}
+
+impl<H> Head<H> for FileHead<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type CheckoutErr = CheckoutErr;
+    type CommitErr = CommitErr;
+
+    async fn checkout(&self) -> Result<Option<Hash<H>>, Self::CheckoutErr> {
+        let path = self.path.clone();
+        let stored = unblock(move || read_head(&path)).await?;
+        Ok(stored.map(Hash::new))
+    }
+
+    async fn commit(
+        &self,
+        old_hash: Option<Hash<H>>,
+        new_hash: Hash<H>,
+    ) -> Result<CommitResult<H>, Self::CommitErr> {
+        let path = self.path.clone();
+        let old = old_hash.map(|hash| hash.bytes);
+        let new = new_hash.bytes;
+        match unblock(move || swap_head(&path, old, new)).await? {
+            None => Ok(CommitResult::Success()),
+            Some(stored) => Ok(CommitResult::Conflict(stored.map(Hash::new))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::StreamExt;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tribles-{}-{}", name, tmp_name()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn repo_roundtrip() {
+        let dir = test_dir("filerepo");
+        let repo: FileRepo<blake3::Hasher> = FileRepo::open(&dir).unwrap();
+        let blob: Bytes = b"hello tribles".to_vec().into();
+
+        let hash = block_on(repo.push(blob.clone())).unwrap();
+        assert_eq!(hash, block_on(repo.push(blob.clone())).unwrap());
+        assert_eq!(&block_on(repo.pull(hash)).unwrap()[..], &blob[..]);
+
+        let listed: Vec<_> = block_on(repo.list().collect::<Vec<_>>())
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(listed, vec![hash]);
+
+        fs::write(repo.blob_path(&hash.bytes), b"hello trible").unwrap();
+        assert!(matches!(block_on(repo.pull(hash)), Err(PullErr::Corrupt)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn head_compare_and_swap() {
+        let dir = test_dir("filehead");
+        let head: FileHead<blake3::Hasher> = FileHead::open(dir.join("head")).unwrap();
+        let a = Hash::new([1; 32]);
+        let b = Hash::new([2; 32]);
+
+        assert_eq!(block_on(head.checkout()).unwrap(), None);
+        assert!(matches!(
+            block_on(head.commit(None, a)).unwrap(),
+            CommitResult::Success()
+        ));
+        assert!(matches!(
+            block_on(head.commit(None, b)).unwrap(),
+            CommitResult::Conflict(Some(h)) if h == a
+        ));
+        assert!(matches!(
+            block_on(head.commit(Some(a), b)).unwrap(),
+            CommitResult::Success()
+        ));
+        assert_eq!(block_on(head.checkout()).unwrap(), Some(b));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,6 @@
+// See the `repo` module for why the traits use `async fn`.
+#![allow(async_fn_in_trait)]
+
 use crate::types::Hash;
 
 #[derive(Debug)]
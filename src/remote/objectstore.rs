@@ -2,22 +2,37 @@ use std::array::TryFromSliceError;
 use std::convert::TryInto;
 use std::error::Error;
 use std::fmt;
+use std::fs::{self, File};
+use std::io;
 use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
-use futures::{Stream, StreamExt};
+use blocking::Unblock;
+use futures::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
 use anybytes::Bytes;
 
 use digest::{typenum::U32, Digest};
 use object_store::{self, parse_url, path::Path, ObjectStore, PutMode};
-use object_store::UpdateVersion;
+use object_store::{MultipartUpload, UpdateVersion};
 use url::Url;
 
 use hex::FromHex;
 
-use crate::{types::Hash, Value};
+use crate::{id::ufoid, types::Hash, Value};
 
 use super::head::{CommitResult, Head};
-use super::repo::{List, Pull, Push};
+use super::repo::{Contains, Forget, List, Modified, Pull, Push};
+
+/// Streamed blobs are uploaded in parts of this many bytes,
+/// which is the minimum most stores accept for all but the last part.
+const PART_LEN: usize = 5 * 1024 * 1024;
+
+/// Pushing a blob that is already stored only puts an empty marker
+/// under this child of the prefix, whose modification time counts
+/// as that of the blob, instead of uploading the blob again.
+const TOUCHED: &str = "touched";
 
 pub struct ObjectRepo<H> {
     store: Box<dyn ObjectStore>,
@@ -34,8 +49,21 @@ impl<H> ObjectRepo<H> {
             _hasher: PhantomData,
         })
     }
+
+    fn touched(&self, digest: &Value) -> Path {
+        self.prefix.child(TOUCHED).child(hex::encode(digest))
+    }
+
+    /// Refreshes the modification time of a stored blob.
+    async fn touch(&self, digest: &Value) -> Result<(), object_store::Error> {
+        self.store
+            .put(&self.touched(digest), bytes::Bytes::new().into())
+            .await?;
+        Ok(())
+    }
 }
 
+#[derive(Debug)]
 pub enum ListErr {
     List(object_store::Error),
     NotAFile(&'static str),
@@ -49,8 +77,10 @@ where
     type Err = ListErr;
 
     fn list<'a>(&'a self) -> impl Stream<Item = Result<Hash<H>, Self::Err>> {
+        let touched = self.prefix.child(TOUCHED);
         self.store
             .list(Some(&self.prefix))
+            .try_filter(move |meta| future::ready(!meta.location.prefix_matches(&touched)))
             .map(|r| match r {
                 Ok(meta) => {
                     let blob_name = meta
@@ -66,44 +96,273 @@ where
     }
 }
 
+#[derive(Debug)]
+pub enum PullErr {
+    Store(object_store::Error),
+    /// The stored content does not match its hash.
+    Corrupt,
+}
+
+impl fmt::Display for PullErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Store(e) => write!(f, "pull failed: {}", e),
+            Self::Corrupt => write!(f, "pull failed: blob does not match its hash"),
+        }
+    }
+}
+
+impl Error for PullErr {}
+
+impl From<object_store::Error> for PullErr {
+    fn from(err: object_store::Error) -> Self {
+        Self::Store(err)
+    }
+}
+
 impl<H> Pull<H> for ObjectRepo<H>
 where
     H: Digest<OutputSize = U32>,
 {
-    type Err = object_store::Error;
+    type Err = PullErr;
 
     async fn pull(&self, hash: Hash<H>) -> Result<Bytes, Self::Err> {
         let path = self.prefix.child(hex::encode(hash.bytes));
         let result = self.store.get(&path).await?;
         let object = result.bytes().await?;
+        let digest: Value = H::digest(&object).into();
+        if digest != hash.bytes {
+            return Err(PullErr::Corrupt);
+        }
         Ok(object.into())
     }
+
+    /// Streams the blob and checks its hash along the way,
+    /// so the last item is an error if the content is corrupt.
+    fn pull_stream(&self, hash: Hash<H>) -> impl Stream<Item = Result<Bytes, Self::Err>> {
+        let path = self.prefix.child(hex::encode(hash.bytes));
+        let chunks = Box::pin(
+            stream::once(async move { self.store.get(&path).await })
+                .map_ok(|result| result.into_stream())
+                .try_flatten(),
+        );
+        stream::unfold(Some((chunks, H::new())), move |state| async move {
+            let (mut chunks, mut hasher) = state?;
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    hasher.update(&chunk);
+                    Some((Ok(chunk.into()), Some((chunks, hasher))))
+                }
+                Some(Err(e)) => Some((Err(PullErr::Store(e)), None)),
+                None => {
+                    let digest: Value = hasher.finalize().into();
+                    (digest != hash.bytes).then(|| (Err(PullErr::Corrupt), None))
+                }
+            }
+        })
+    }
 }
 
-impl<H> Push<H> for ObjectRepo<H>
+impl<H> Contains<H> for ObjectRepo<H>
 where
     H: Digest<OutputSize = U32>,
 {
     type Err = object_store::Error;
 
+    async fn contains(&self, hash: Hash<H>) -> Result<bool, Self::Err> {
+        let path = self.prefix.child(hex::encode(hash.bytes));
+        match self.store.head(&path).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PushErr {
+    Store(object_store::Error),
+    /// Spooling a streamed blob to a local file failed.
+    IO(io::Error),
+}
+
+impl fmt::Display for PushErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Store(e) => write!(f, "push failed: {}", e),
+            Self::IO(e) => write!(f, "push failed: {}", e),
+        }
+    }
+}
+
+impl Error for PushErr {}
+
+impl From<object_store::Error> for PushErr {
+    fn from(err: object_store::Error) -> Self {
+        Self::Store(err)
+    }
+}
+
+impl From<io::Error> for PushErr {
+    fn from(err: io::Error) -> Self {
+        Self::IO(err)
+    }
+}
+
+impl<H> Push<H> for ObjectRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = PushErr;
+
     async fn push(&self, blob: Bytes) -> Result<Hash<H>, Self::Err> {
         let digest: Value = H::digest(&blob).into();
         let path = self.prefix.child(hex::encode(digest));
+        let payload = bytes::Bytes::copy_from_slice(&blob); // This copy could be avoided if bytes::Bytes was open...
         let put_result = self
             .store
-            .put_opts(
-                &path,
-                bytes::Bytes::copy_from_slice(&blob).into(), // This copy could be avoided if bytes::Bytes was open...
-                PutMode::Create.into(),
-            )
+            .put_opts(&path, payload.clone().into(), PutMode::Create.into())
             .await;
         match put_result {
-            Ok(_) | Err(object_store::Error::AlreadyExists { .. }) => Ok(Hash::new(digest)),
+            Ok(_) => Ok(Hash::new(digest)),
+            // Touching the blob keeps it within the grace period of garbage collection.
+            Err(object_store::Error::AlreadyExists { .. }) => {
+                self.touch(&digest).await?;
+                Ok(Hash::new(digest))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Hashes the chunks while spooling them to a temporary local file,
+    /// and uploads the file to the blob's key with a multipart put once
+    /// the hash is known. Only a single part is buffered at a time.
+    ///
+    /// Stores like S3 can't rename an object without copying it, which
+    /// fails for objects above 5GB, so nothing is staged in the store,
+    /// and a failed upload is aborted instead of leaving an object behind.
+    /// The spool file is named `tribles-upload-<id>`, so files left behind
+    /// by a process that died during a push can be found and removed.
+    async fn push_stream<S>(&self, chunks: S) -> Result<Hash<H>, Self::Err>
+    where
+        S: Stream<Item = Bytes>,
+    {
+        let spool = std::env::temp_dir().join(format!("tribles-upload-{}", hex::encode(ufoid())));
+        let result = self.spool_and_upload(spool.clone(), chunks).await;
+        // The spool doesn't exist if creating it failed.
+        let _ = blocking::unblock(move || fs::remove_file(spool)).await;
+        result
+    }
+}
+
+impl<H> ObjectRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    async fn spool_and_upload<S>(&self, spool: PathBuf, chunks: S) -> Result<Hash<H>, PushErr>
+    where
+        S: Stream<Item = Bytes>,
+    {
+        let file = blocking::unblock(move || {
+            File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(spool)
+        })
+        .await?;
+        let mut file = Unblock::new(file);
+
+        let mut hasher = H::new();
+        futures::pin_mut!(chunks);
+        while let Some(chunk) = chunks.next().await {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        let digest: Value = hasher.finalize().into();
+
+        let path = self.prefix.child(hex::encode(digest));
+        match self.store.head(&path).await {
+            Ok(_) => {
+                self.touch(&digest).await?;
+                return Ok(Hash::new(digest));
+            }
+            Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        file.seek(io::SeekFrom::Start(0)).await?;
+        let mut upload = self.store.put_multipart(&path).await?;
+        match upload_parts(upload.as_mut(), &mut file).await {
+            Ok(()) => {
+                upload.complete().await?;
+                Ok(Hash::new(digest))
+            }
+            Err(e) => {
+                let _ = upload.abort().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn upload_parts(
+    upload: &mut dyn MultipartUpload,
+    file: &mut Unblock<File>,
+) -> Result<(), PushErr> {
+    let mut parts = 0;
+    loop {
+        let mut part = Vec::with_capacity(PART_LEN);
+        (&mut *file).take(PART_LEN as u64).read_to_end(&mut part).await?;
+        // An upload needs at least one part, even if the blob is empty.
+        if part.is_empty() && parts > 0 {
+            return Ok(());
+        }
+        let last = part.len() < PART_LEN;
+        upload.put_part(part.into()).await?;
+        parts += 1;
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+impl<H> Modified<H> for ObjectRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = object_store::Error;
+
+    async fn modified(&self, hash: Hash<H>) -> Result<SystemTime, Self::Err> {
+        let path = self.prefix.child(hex::encode(hash.bytes));
+        let blob = self.store.head(&path).await?;
+        match self.store.head(&self.touched(&hash.bytes)).await {
+            Ok(touched) => Ok(blob.last_modified.max(touched.last_modified).into()),
+            Err(object_store::Error::NotFound { .. }) => Ok(blob.last_modified.into()),
             Err(e) => Err(e),
         }
     }
 }
 
+impl<H> Forget<H> for ObjectRepo<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = object_store::Error;
+
+    async fn forget(&self, hash: Hash<H>) -> Result<(), Self::Err> {
+        let path = self.prefix.child(hex::encode(hash.bytes));
+        for path in [path, self.touched(&hash.bytes)] {
+            match self.store.delete(&path).await {
+                Ok(_) | Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ObjectHead<H> {
     store: Box<dyn ObjectStore>,
     path: Path,
@@ -275,3 +534,73 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::hash::Blake3;
+    use futures::executor::block_on;
+    use rand::{thread_rng, RngCore};
+
+    #[test]
+    fn streamed_roundtrip() {
+        let repo: ObjectRepo<Blake3> =
+            ObjectRepo::with_url(&Url::parse("memory:///").unwrap()).unwrap();
+
+        let mut content = vec![0; PART_LEN + PART_LEN / 2];
+        thread_rng().fill_bytes(&mut content);
+        let chunks: Vec<Bytes> = content
+            .chunks(64 * 1024)
+            .map(|chunk| chunk.to_vec().into())
+            .collect();
+
+        let hash: Hash<Blake3> = block_on(repo.push_stream(stream::iter(chunks))).unwrap();
+        assert_eq!(hash.bytes, Value::from(Blake3::digest(&content)));
+
+        let pulled: Vec<Bytes> = block_on(repo.pull_stream(hash).try_collect()).unwrap();
+        let pulled: Vec<u8> = pulled
+            .iter()
+            .flat_map(|chunk| chunk.iter().copied())
+            .collect();
+        assert_eq!(pulled, content);
+
+        // Nothing but the blob is left in the store.
+        let listed: Vec<Hash<Blake3>> = block_on(repo.list().try_collect()).unwrap();
+        assert_eq!(listed, vec![hash]);
+
+        // Content that doesn't match its hash is reported, by the stream at its end.
+        let key = repo.prefix.child(hex::encode(hash.bytes));
+        block_on(repo.store.put(&key, bytes::Bytes::from_static(b"forged").into())).unwrap();
+        let pulled: Result<Vec<Bytes>, _> = block_on(repo.pull_stream(hash).try_collect());
+        assert!(matches!(pulled, Err(PullErr::Corrupt)));
+        assert!(matches!(block_on(repo.pull(hash)), Err(PullErr::Corrupt)));
+
+        let empty: Hash<Blake3> = block_on(repo.push_stream(stream::empty())).unwrap();
+        assert!(block_on(repo.pull(empty)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeated_push_touches() {
+        let repo: ObjectRepo<Blake3> =
+            ObjectRepo::with_url(&Url::parse("memory:///").unwrap()).unwrap();
+        let blob = Bytes::from(b"touched".to_vec());
+        let hash: Hash<Blake3> = block_on(repo.push(blob.clone())).unwrap();
+        let key = repo.prefix.child(hex::encode(hash.bytes));
+        let stored = block_on(repo.store.head(&key)).unwrap();
+        let pushed = block_on(repo.modified(hash)).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        block_on(repo.push(blob.clone())).unwrap();
+        block_on(repo.push_stream(stream::iter([blob]))).unwrap();
+        // The blob itself isn't written again, but counts as modified.
+        assert_eq!(block_on(repo.store.head(&key)).unwrap(), stored);
+        assert!(block_on(repo.modified(hash)).unwrap() > pushed);
+
+        let listed: Vec<Hash<Blake3>> = block_on(repo.list().try_collect()).unwrap();
+        assert_eq!(listed, vec![hash]);
+
+        block_on(repo.forget(hash)).unwrap();
+        let remaining: Vec<_> = block_on(repo.store.list(None).try_collect()).unwrap();
+        assert!(remaining.is_empty());
+    }
+}
@@ -1,14 +1,27 @@
+// The traits use `async fn` because the crate is executor agnostic, and
+// requiring `Send` futures would rule out single threaded and in-memory
+// implementations that hold non-`Send` state across awaits.
+#![allow(async_fn_in_trait)]
+
 use std::{
-    convert::Infallible,
+    collections::{HashSet, VecDeque},
+    convert::{Infallible, TryInto},
     error::Error,
     fmt::{self, Debug},
+    time::{Duration, SystemTime},
 };
 
 use digest::{typenum::U32, Digest};
-use futures::{stream, Stream, StreamExt};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use anybytes::Bytes;
 
-use crate::{types::Hash, BlobParseError, BlobSet};
+use super::head::Head;
+use crate::{
+    trible::{TRIBLE_LEN, V_END, V_START},
+    triblearchive::SimpleArchive,
+    types::{ChunkTree, Hash},
+    BlobParseError, BlobSet, Bloblike,
+};
 
 #[derive(Debug)]
 pub enum TransferError<ListErr, LoadErr, StoreErr> {
@@ -71,6 +84,297 @@ where
     r
 }
 
+#[derive(Debug)]
+pub enum SyncError<SourceContainsErr, SourcePullErr, TargetContainsErr, PushErr> {
+    SourceContains(SourceContainsErr),
+    SourcePull(SourcePullErr),
+    TargetContains(TargetContainsErr),
+    Push(PushErr),
+}
+
+impl<SourceContainsErr, SourcePullErr, TargetContainsErr, PushErr> fmt::Display
+    for SyncError<SourceContainsErr, SourcePullErr, TargetContainsErr, PushErr>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to sync blob")
+    }
+}
+
+impl<SourceContainsErr, SourcePullErr, TargetContainsErr, PushErr> Error
+    for SyncError<SourceContainsErr, SourcePullErr, TargetContainsErr, PushErr>
+where
+    SourceContainsErr: Debug + Error + 'static,
+    SourcePullErr: Debug + Error + 'static,
+    TargetContainsErr: Debug + Error + 'static,
+    PushErr: Debug + Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::SourceContains(e) => Some(e),
+            Self::SourcePull(e) => Some(e),
+            Self::TargetContains(e) => Some(e),
+            Self::Push(e) => Some(e),
+        }
+    }
+}
+
+/// The progress reported by [sync].
+#[derive(Debug)]
+pub enum SyncProgress<H> {
+    /// The blob was missing in the target and has been transferred.
+    Transferred(Hash<H>),
+    /// The target already had the blob, and therefore everything it references.
+    Present(Hash<H>),
+}
+
+pub type SyncErr<H, BS, BT> = SyncError<
+    <BS as Contains<H>>::Err,
+    <BS as Pull<H>>::Err,
+    <BT as Contains<H>>::Err,
+    <BT as Push<H>>::Err,
+>;
+
+/// Returns the hashes that `blob` might refer to, i.e. the trible values
+/// if it is a [SimpleArchive] and the entries if it is a [ChunkTree].
+///
+/// Like [BlobSet::keep] this is conservative, as values are returned
+/// irrespective of their attribute's type, and blobs that merely look
+/// like one of these types are walked too.
+fn referenced_hashes<H>(blob: &Bytes) -> Vec<Hash<H>> {
+    let mut hashes = Vec::new();
+    if SimpleArchive::from_blob(blob.clone()).is_ok() {
+        hashes.extend(
+            blob.chunks_exact(TRIBLE_LEN)
+                .map(|trible| Hash::new(trible[V_START..=V_END].try_into().unwrap())),
+        );
+    }
+    // Chunked blobs are made of a tree of nodes that name their children by
+    // hash, so following the entries transfers and keeps the whole content.
+    if let Ok(tree) = ChunkTree::from_blob(blob.clone()) {
+        hashes.extend(tree.entries().map(|(hash, _)| Hash::new(hash)));
+    }
+    hashes
+}
+
+/// A blob of the source whose references are still being transferred.
+struct SyncFrame<H> {
+    hash: Hash<H>,
+    blob: Bytes,
+    references: Vec<Hash<H>>,
+}
+
+/// Transfers the blobs reachable from `root` that the target lacks.
+///
+/// Blobs that are [SimpleArchive]s, like commits and their payloads, are
+/// walked by following every trible value that names a blob of the source,
+/// and [ChunkTree]s by following their entries.
+///
+/// Blobs are pushed after everything they reference, so a blob in the target
+/// implies that everything reachable from it is there too, and the walk
+/// stops at the blobs the target already has. Consequently only the missing
+/// blobs are pulled, and repeating an interrupted sync resumes where it stopped.
+/// This relies on every writer of the target pushing in the same order.
+pub fn sync<'a, BS, BT, H>(
+    source: &'a BS,
+    target: &'a BT,
+    root: Hash<H>,
+) -> impl Stream<Item = Result<SyncProgress<H>, SyncErr<H, BS, BT>>> + 'a
+where
+    BS: Contains<H> + Pull<H>,
+    BT: Contains<H> + Push<H>,
+    H: 'static + Digest<OutputSize = U32>,
+{
+    stream::unfold(
+        Some((Some(root), Vec::new(), HashSet::from([root]))),
+        move |state| async move {
+            let (mut next, mut stack, mut seen) = state?;
+            loop {
+                if let Some(hash) = next.take() {
+                    match sync_visit(source, target, hash, hash == root).await {
+                        Ok(SyncVisit::Present) => {
+                            return Some((
+                                Ok(SyncProgress::Present(hash)),
+                                Some((None, stack, seen)),
+                            ))
+                        }
+                        Ok(SyncVisit::Missing(frame)) => stack.push(frame),
+                        Ok(SyncVisit::Unknown) => {}
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+                let frame: &mut SyncFrame<H> = stack.last_mut()?;
+                if let Some(reference) = frame.references.pop() {
+                    if seen.insert(reference) {
+                        next = Some(reference);
+                    }
+                    continue;
+                }
+                let frame = stack.pop().unwrap();
+                return match target.push(frame.blob).await {
+                    Ok(_) => Some((
+                        Ok(SyncProgress::Transferred(frame.hash)),
+                        Some((None, stack, seen)),
+                    )),
+                    Err(e) => Some((Err(SyncError::Push(e)), None)),
+                };
+            }
+        },
+    )
+}
+
+enum SyncVisit<H> {
+    /// The target has the blob.
+    Present,
+    /// The target lacks the blob, which has been pulled from the source.
+    Missing(SyncFrame<H>),
+    /// The value only looked like a hash and names no blob of the source.
+    Unknown,
+}
+
+async fn sync_visit<BS, BT, H>(
+    source: &BS,
+    target: &BT,
+    hash: Hash<H>,
+    is_root: bool,
+) -> Result<SyncVisit<H>, SyncErr<H, BS, BT>>
+where
+    BS: Contains<H> + Pull<H>,
+    BT: Contains<H> + Push<H>,
+    H: 'static + Digest<OutputSize = U32>,
+{
+    if target.contains(hash).await.map_err(SyncError::TargetContains)? {
+        return Ok(SyncVisit::Present);
+    }
+    // The root must exist, so pulling it reports a missing root as an error.
+    if !is_root && !source.contains(hash).await.map_err(SyncError::SourceContains)? {
+        return Ok(SyncVisit::Unknown);
+    }
+    let blob = source.pull(hash).await.map_err(SyncError::SourcePull)?;
+    let references = referenced_hashes(&blob);
+    Ok(SyncVisit::Missing(SyncFrame {
+        hash,
+        blob,
+        references,
+    }))
+}
+
+#[derive(Debug)]
+pub enum GcError<CheckoutErr, ListErr, ModifiedErr, PullErr, ForgetErr> {
+    Checkout(CheckoutErr),
+    List(ListErr),
+    Modified(ModifiedErr),
+    Pull(PullErr),
+    Forget(ForgetErr),
+}
+
+impl<CheckoutErr, ListErr, ModifiedErr, PullErr, ForgetErr> fmt::Display
+    for GcError<CheckoutErr, ListErr, ModifiedErr, PullErr, ForgetErr>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to collect garbage")
+    }
+}
+
+impl<CheckoutErr, ListErr, ModifiedErr, PullErr, ForgetErr> Error
+    for GcError<CheckoutErr, ListErr, ModifiedErr, PullErr, ForgetErr>
+where
+    CheckoutErr: Debug + Error + 'static,
+    ListErr: Debug + Error + 'static,
+    ModifiedErr: Debug + Error + 'static,
+    PullErr: Debug + Error + 'static,
+    ForgetErr: Debug + Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Checkout(e) => Some(e),
+            Self::List(e) => Some(e),
+            Self::Modified(e) => Some(e),
+            Self::Pull(e) => Some(e),
+            Self::Forget(e) => Some(e),
+        }
+    }
+}
+
+pub type GcErr<H, R, HD> = GcError<
+    <HD as Head<H>>::CheckoutErr,
+    <R as List<H>>::Err,
+    <R as Modified<H>>::Err,
+    <R as Pull<H>>::Err,
+    <R as Forget<H>>::Err,
+>;
+
+/// What [collect_garbage] found, or would have deleted in a dry run.
+#[derive(Debug)]
+pub struct GcReport<H> {
+    /// The number of blobs reachable from the registry or a recent blob.
+    pub reachable: usize,
+    /// The number of those blobs that were pushed within the grace period.
+    pub recent: usize,
+    /// The unreachable blobs.
+    pub garbage: Vec<Hash<H>>,
+}
+
+/// Deletes every blob of `repo` that isn't reachable from the head of the
+/// `registry`, or only reports them if `dry_run` is set.
+///
+/// The registry is usually the head of [crate::remote::branch::Branches],
+/// whose archive reaches the commits of every branch.
+/// Reachability is determined like in [sync].
+///
+/// Blobs that were pushed less than `grace` ago are kept together with
+/// everything they reach, because a writer may have pushed them for a
+/// commit that isn't reachable yet, possibly on top of older unreachable
+/// blobs it found present. Writers must therefore move a head within `grace`
+/// of pushing the blobs it reaches, and pushing a blob that is already
+/// stored refreshes its modification time.
+pub async fn collect_garbage<R, HD, H>(
+    repo: &R,
+    registry: &HD,
+    grace: Duration,
+    dry_run: bool,
+) -> Result<GcReport<H>, GcErr<H, R, HD>>
+where
+    R: List<H> + Modified<H> + Pull<H> + Forget<H>,
+    HD: Head<H>,
+    H: Digest<OutputSize = U32>,
+{
+    let head = registry.checkout().await.map_err(GcError::Checkout)?;
+    let stored: HashSet<Hash<H>> = repo.list().try_collect().await.map_err(GcError::List)?;
+
+    let cutoff = SystemTime::now().checked_sub(grace);
+    let mut queue: VecDeque<Hash<H>> = VecDeque::new();
+    for &hash in &stored {
+        let modified = repo.modified(hash).await.map_err(GcError::Modified)?;
+        if cutoff.map_or(true, |cutoff| modified > cutoff) {
+            queue.push_back(hash);
+        }
+    }
+    let recent = queue.len();
+    queue.extend(head.filter(|head| stored.contains(head)));
+
+    let mut marked: HashSet<Hash<H>> = queue.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        let blob = repo.pull(hash).await.map_err(GcError::Pull)?;
+        for value in referenced_hashes(&blob) {
+            if stored.contains(&value) && marked.insert(value) {
+                queue.push_back(value);
+            }
+        }
+    }
+
+    let garbage: Vec<Hash<H>> = stored.difference(&marked).copied().collect();
+    if !dry_run {
+        for &hash in &garbage {
+            repo.forget(hash).await.map_err(GcError::Forget)?;
+        }
+    }
+    Ok(GcReport {
+        reachable: marked.len(),
+        recent,
+        garbage,
+    })
+}
+
 #[derive(Debug)]
 pub enum GetError<E> {
     Load(E),
@@ -86,12 +390,64 @@ pub trait Pull<H> {
     type Err;
 
     async fn pull(&self, hash: Hash<H>) -> Result<Bytes, Self::Err>;
+
+    /// Pulls the blob as a stream of consecutive chunks.
+    ///
+    /// Repos that can read blobs incrementally should override this,
+    /// by default the whole blob is pulled as a single chunk.
+    fn pull_stream(&self, hash: Hash<H>) -> impl Stream<Item = Result<Bytes, Self::Err>> {
+        stream::once(self.pull(hash))
+    }
+}
+
+pub trait Contains<H> {
+    type Err;
+
+    /// Checks whether the blob is stored, without pulling it.
+    async fn contains(&self, hash: Hash<H>) -> Result<bool, Self::Err>;
 }
 
 pub trait Push<H> {
     type Err;
 
     async fn push(&self, blob: Bytes) -> Result<Hash<H>, Self::Err>;
+
+    /// Pushes the concatenation of the chunks as a single blob.
+    ///
+    /// Repos that can hash and store blobs incrementally should override this,
+    /// by default the chunks are collected and pushed at once.
+    ///
+    /// Overrides may have to keep the chunks somewhere until the hash that
+    /// names the blob is known. [ObjectRepo](super::objectstore::ObjectRepo)
+    /// spools them to a file in [std::env::temp_dir], so it needs free local
+    /// disk space for the whole blob, and the file is left behind if the
+    /// process dies during the push.
+    async fn push_stream<S>(&self, chunks: S) -> Result<Hash<H>, Self::Err>
+    where
+        S: Stream<Item = Bytes>,
+    {
+        let blob = chunks
+            .fold(Vec::new(), |mut blob, chunk| async move {
+                blob.extend_from_slice(&chunk);
+                blob
+            })
+            .await;
+        self.push(blob.into()).await
+    }
+}
+
+pub trait Modified<H> {
+    type Err;
+
+    /// Returns when the blob was last pushed.
+    async fn modified(&self, hash: Hash<H>) -> Result<SystemTime, Self::Err>;
+}
+
+pub trait Forget<H> {
+    type Err;
+
+    /// Deletes the blob, which is not an error if it doesn't exist.
+    async fn forget(&self, hash: Hash<H>) -> Result<(), Self::Err>;
 }
 
 pub trait Repo<H>: List<H> + Pull<H> + Push<H> {
@@ -143,3 +499,134 @@ where
             .map_or(Err(NotFoundErr()), |b| Ok(b.clone()))
     }
 }
+
+impl<H> Contains<H> for BlobSet<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    type Err = Infallible;
+
+    async fn contains(&self, hash: Hash<H>) -> Result<bool, Self::Err> {
+        Ok(self.get_raw(hash).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::commit::CommitBuilder;
+    use crate::remote::branch::Branches;
+    use crate::remote::filestore::{FileHead, FileRepo};
+    use crate::types::{hash::Blake3, ZCString};
+    use crate::{id::ufoid, Handle, TribleSet, NS};
+    use ed25519_dalek::SigningKey;
+    use futures::executor::block_on;
+
+    NS! {
+        pub namespace notes {
+            "E07D037B8977FE1CB721EC05117AC87F" as text: Handle<Blake3, ZCString>;
+        }
+    }
+
+    #[test]
+    fn sync_only_missing() {
+        let mut source: BlobSet<Blake3> = BlobSet::new();
+        source.put(ZCString::from("unreachable".to_string()));
+
+        let commit_note = |source: &mut BlobSet<Blake3>, text: &str, parent| {
+            let payload: TribleSet = notes::entity!({
+                text: source.put(ZCString::from(text.to_string()))
+            });
+            let payload = source.put(SimpleArchive::from(&payload));
+            let mut builder = CommitBuilder::new(payload);
+            if let Some(parent) = parent {
+                builder = builder.parent(parent);
+            }
+            let tribles = builder.sign(SigningKey::from_bytes(&[7; 32])).unwrap();
+            source.put(SimpleArchive::from(&tribles))
+        };
+        let first = commit_note(&mut source, "first", None);
+        let second = commit_note(&mut source, "second", Some(first));
+        let head = second.hash;
+
+        let dir = std::env::temp_dir().join(format!("tribles-sync-{}", hex::encode(ufoid())));
+        let target: FileRepo<Blake3> = FileRepo::open(&dir).unwrap();
+
+        let progress: Vec<_> =
+            block_on(sync(&source, &target, head).try_collect::<Vec<_>>()).unwrap();
+        // Two commits with a payload and a text blob each.
+        assert_eq!(progress.len(), 6);
+        assert!(matches!(progress[5], SyncProgress::Transferred(h) if h == head));
+        assert!(progress
+            .iter()
+            .all(|p| matches!(p, SyncProgress::Transferred(_))));
+
+        // The walk stops at the root if the target already has it.
+        let progress: Vec<_> =
+            block_on(sync(&source, &target, head).try_collect::<Vec<_>>()).unwrap();
+        assert_eq!(progress.len(), 1);
+        assert!(matches!(progress[0], SyncProgress::Present(h) if h == head));
+
+        // And at the parent for a new commit, without pulling the old history.
+        let third = commit_note(&mut source, "third", Some(second));
+        let source: BlobSet<Blake3> = source
+            .iter_raw()
+            .filter(|(hash, _)| **hash != first.hash)
+            .map(|(hash, blob)| (*hash, blob.clone()))
+            .collect();
+        let progress: Vec<_> =
+            block_on(sync(&source, &target, third.hash).try_collect::<Vec<_>>()).unwrap();
+        assert_eq!(progress.len(), 4);
+        assert!(progress
+            .iter()
+            .any(|p| matches!(p, SyncProgress::Present(h) if *h == head)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn garbage_collection() {
+        let dir = std::env::temp_dir().join(format!("tribles-gc-{}", hex::encode(ufoid())));
+        let repo: FileRepo<Blake3> = FileRepo::open(&dir).unwrap();
+        let registry: FileHead<Blake3> = FileHead::open(dir.join("branches")).unwrap();
+        let branches = Branches::new(
+            FileRepo::<Blake3>::open(&dir).unwrap(),
+            FileHead::<Blake3>::open(dir.join("branches")).unwrap(),
+        );
+
+        let put = |text: &str| {
+            let blob = ZCString::from(text.to_string()).into_blob();
+            block_on(repo.push(blob)).unwrap()
+        };
+        let garbage = put("garbage");
+        let text = put("reachable");
+        let payload: TribleSet = notes::entity!({ text: unsafe { Handle::new(text) } });
+        let root = block_on(repo.push(SimpleArchive::from(&payload).into_blob())).unwrap();
+        block_on(branches.fork("main", root)).unwrap();
+
+        let hour = Duration::from_secs(60 * 60);
+        let report = block_on(collect_garbage(&repo, &registry, hour, false)).unwrap();
+        assert_eq!(report.recent, 4);
+        assert!(report.garbage.is_empty());
+        assert!(block_on(repo.pull(garbage)).is_ok());
+
+        let report = block_on(collect_garbage(&repo, &registry, Duration::ZERO, true)).unwrap();
+        assert_eq!(report.reachable, 3);
+        assert_eq!(report.recent, 0);
+        assert_eq!(report.garbage, vec![garbage]);
+        assert!(block_on(repo.pull(garbage)).is_ok());
+
+        let report = block_on(collect_garbage(&repo, &registry, Duration::ZERO, false)).unwrap();
+        assert_eq!(report.garbage, vec![garbage]);
+        assert!(block_on(repo.pull(garbage)).is_err());
+        assert!(block_on(repo.pull(text)).is_ok());
+
+        // Only the new registry remains reachable once the branch is gone.
+        block_on(branches.delete("main")).unwrap();
+        let report = block_on(collect_garbage(&repo, &registry, Duration::ZERO, false)).unwrap();
+        assert_eq!(report.reachable, 1);
+        assert_eq!(report.garbage.len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use std::error::Error;
+use std::fmt::{self, Debug};
+
+use ed25519_dalek::SigningKey;
+
+use crate::{
+    meta::commit::{commit_entity, load, payload, CommitBuilder, LoadErr, ValidationError},
+    triblearchive::SimpleArchive,
+    types::{hash::Blake3, ShortString},
+    BlobParseError, BlobSet, Bloblike, Handle, TribleSet,
+};
+
+use super::head::{CommitResult, Head};
+use super::repo::{Pull, Push};
+
+#[derive(Debug)]
+pub enum WorkspaceErr<PullErr, PushErr, CheckoutErr, CommitErr> {
+    Pull(PullErr),
+    Push(PushErr),
+    Checkout(CheckoutErr),
+    Commit(CommitErr),
+    Parse(BlobParseError),
+    Validation(ValidationError),
+    /// The head kept moving for the given number of attempts.
+    TooManyConflicts(usize),
+}
+
+impl<PullErr, PushErr, CheckoutErr, CommitErr> fmt::Display
+    for WorkspaceErr<PullErr, PushErr, CheckoutErr, CommitErr>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pull(_) => write!(f, "failed to pull blob"),
+            Self::Push(_) => write!(f, "failed to push blob"),
+            Self::Checkout(_) => write!(f, "failed to checkout head"),
+            Self::Commit(_) => write!(f, "failed to commit head"),
+            Self::Parse(_) => write!(f, "malformed archive"),
+            Self::Validation(_) => write!(f, "invalid commit"),
+            Self::TooManyConflicts(attempts) => {
+                write!(f, "head still conflicting after {} attempts", attempts)
+            }
+        }
+    }
+}
+
+impl<PullErr, PushErr, CheckoutErr, CommitErr> Error
+    for WorkspaceErr<PullErr, PushErr, CheckoutErr, CommitErr>
+where
+    PullErr: Debug + Error + 'static,
+    PushErr: Debug + Error + 'static,
+    CheckoutErr: Debug + Error + 'static,
+    CommitErr: Debug + Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Pull(e) => Some(e),
+            Self::Push(e) => Some(e),
+            Self::Checkout(e) => Some(e),
+            Self::Commit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl<PullErr, PushErr, CheckoutErr, CommitErr> From<LoadErr<PullErr>>
+    for WorkspaceErr<PullErr, PushErr, CheckoutErr, CommitErr>
+{
+    fn from(err: LoadErr<PullErr>) -> Self {
+        match err {
+            LoadErr::Pull(e) => Self::Pull(e),
+            LoadErr::Parse(e) => Self::Parse(e),
+            LoadErr::Validation(e) => Self::Validation(e),
+        }
+    }
+}
+
+pub type WorkspaceResult<T, R, HD> = Result<
+    T,
+    WorkspaceErr<
+        <R as Pull<Blake3>>::Err,
+        <R as Push<Blake3>>::Err,
+        <HD as Head<Blake3>>::CheckoutErr,
+        <HD as Head<Blake3>>::CommitErr,
+    >,
+>;
+
+/// A local working copy of the commit a [Head] points to.
+///
+/// Added and removed tribles and blobs are accumulated locally and only pushed
+/// on [Workspace::commit]. When the head moved in the meantime, the other commit
+/// becomes the new base, the local additions and removals are applied to its
+/// payload, and the commit is retried on top of it.
+/// Tribles the other commit removed therefore stay removed,
+/// unless they were added locally.
+pub struct Workspace<'a, R, HD> {
+    repo: &'a R,
+    head: &'a HD,
+    base: Option<Handle<Blake3, SimpleArchive>>,
+    base_tribles: TribleSet,
+    added: TribleSet,
+    removed: TribleSet,
+    blobs: BlobSet<Blake3>,
+}
+
+impl<'a, R, HD> Workspace<'a, R, HD>
+where
+    R: Pull<Blake3> + Push<Blake3>,
+    HD: Head<Blake3>,
+{
+    /// Creates a workspace for the commit the head currently points to.
+    pub async fn checkout(repo: &'a R, head: &'a HD) -> WorkspaceResult<Self, R, HD> {
+        let mut workspace = Workspace {
+            repo,
+            head,
+            base: None,
+            base_tribles: TribleSet::new(),
+            added: TribleSet::new(),
+            removed: TribleSet::new(),
+            blobs: BlobSet::new(),
+        };
+        let base = workspace
+            .head
+            .checkout()
+            .await
+            .map_err(WorkspaceErr::Checkout)?;
+        workspace
+            .rebase(base.map(|hash| unsafe { Handle::new(hash) }))
+            .await?;
+        Ok(workspace)
+    }
+
+    /// The commit this workspace is based on.
+    pub fn base(&self) -> Option<Handle<Blake3, SimpleArchive>> {
+        self.base
+    }
+
+    /// Returns the tribles of the base commit with the local changes applied.
+    pub fn tribles(&self) -> TribleSet {
+        let mut tribles = self.base_tribles.difference(&self.removed);
+        tribles.union(self.added.clone());
+        tribles
+    }
+
+    /// Adds tribles to the next commit.
+    pub fn add(&mut self, tribles: TribleSet) {
+        self.removed = self.removed.difference(&tribles);
+        self.added.union(tribles);
+    }
+
+    /// Removes tribles from the next commit.
+    pub fn remove(&mut self, tribles: TribleSet) {
+        self.added = self.added.difference(&tribles);
+        self.removed.union(tribles);
+    }
+
+    /// Stores a blob that is pushed with the next commit.
+    pub fn put<T>(&mut self, value: T) -> Handle<Blake3, T>
+    where
+        T: Bloblike,
+    {
+        self.blobs.put(value)
+    }
+
+    /// Pushes the local blobs and commits the local tribles on top of the head,
+    /// merging with concurrent commits for at most `max_attempts` attempts.
+    ///
+    /// Returns the new commit, which becomes the base of the workspace.
+    pub async fn commit(
+        &mut self,
+        signing_key: &SigningKey,
+        short_message: Option<ShortString>,
+        max_attempts: usize,
+    ) -> WorkspaceResult<Handle<Blake3, SimpleArchive>, R, HD> {
+        for (_, blob) in self.blobs.iter_raw() {
+            self.repo
+                .push(blob.clone())
+                .await
+                .map_err(WorkspaceErr::Push)?;
+        }
+        self.blobs = BlobSet::new();
+
+        for _ in 0..max_attempts {
+            let tribles = self.tribles();
+            let payload = self.push(SimpleArchive::from(&tribles)).await?;
+            let mut builder = CommitBuilder::new(payload);
+            if let Some(base) = self.base {
+                builder = builder.parent(base);
+            }
+            if let Some(short_message) = &short_message {
+                builder = builder.short_message(short_message.clone());
+            }
+            let commit = builder
+                .sign(signing_key.clone())
+                .map_err(WorkspaceErr::Validation)?;
+            let commit = self.push(SimpleArchive::from(&commit)).await?;
+
+            match self
+                .head
+                .commit(self.base.map(|base| base.hash), commit.hash)
+                .await
+                .map_err(WorkspaceErr::Commit)?
+            {
+                CommitResult::Success() => {
+                    self.base = Some(commit);
+                    self.base_tribles = tribles;
+                    self.added = TribleSet::new();
+                    self.removed = TribleSet::new();
+                    return Ok(commit);
+                }
+                CommitResult::Conflict(other) => {
+                    self.rebase(other.map(|hash| unsafe { Handle::new(hash) }))
+                        .await?;
+                }
+            }
+        }
+        Err(WorkspaceErr::TooManyConflicts(max_attempts))
+    }
+
+    /// Moves the workspace onto `base`, keeping the local changes.
+    async fn rebase(
+        &mut self,
+        base: Option<Handle<Blake3, SimpleArchive>>,
+    ) -> WorkspaceResult<(), R, HD> {
+        let base_tribles = match base {
+            Some(base) => {
+                let commit = load(self.repo, base).await?;
+                let payload = commit_entity(&commit)
+                    .and_then(|id| payload(&commit, id))
+                    .map_err(WorkspaceErr::Validation)?;
+                load(self.repo, payload).await?
+            }
+            None => TribleSet::new(),
+        };
+        self.base = base;
+        self.base_tribles = base_tribles;
+        Ok(())
+    }
+
+    async fn push<T>(&self, value: T) -> WorkspaceResult<Handle<Blake3, T>, R, HD>
+    where
+        T: Bloblike,
+    {
+        let hash = self
+            .repo
+            .push(value.into_blob())
+            .await
+            .map_err(WorkspaceErr::Push)?;
+        Ok(unsafe { Handle::new(hash) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::filestore::{FileHead, FileRepo};
+    use crate::{id::ufoid, NS};
+    use futures::executor::block_on;
+    use std::convert::TryInto;
+
+    NS! {
+        pub namespace notes {
+            "2E734CBBC518588ABB0138C1ABB6FE0E" as text: ShortString;
+        }
+    }
+
+    #[test]
+    fn concurrent_commits_merge() {
+        let dir = std::env::temp_dir().join(format!("tribles-workspace-{}", hex::encode(ufoid())));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo: FileRepo<Blake3> = FileRepo::open(dir.join("repo")).unwrap();
+        let head: FileHead<Blake3> = FileHead::open(dir.join("head")).unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+
+        let mut first = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        let mut second = block_on(Workspace::checkout(&repo, &head)).unwrap();
+
+        let a = notes::entity!({ text: "a".try_into().unwrap() });
+        let b = notes::entity!({ text: "b".try_into().unwrap() });
+        first.add(a.clone());
+        second.add(b.clone());
+
+        let first_commit = block_on(first.commit(&key, None, 1)).unwrap();
+        // The second workspace is stale, so its first attempt conflicts.
+        assert!(matches!(
+            block_on(second.commit(&key, None, 1)),
+            Err(WorkspaceErr::TooManyConflicts(1))
+        ));
+        let second_commit = block_on(second.commit(&key, None, 2)).unwrap();
+        assert_eq!(block_on(head.checkout()).unwrap(), Some(second_commit.hash));
+
+        let commit = block_on(load(&repo, second_commit)).unwrap();
+        assert_eq!(
+            crate::meta::commit::parents(&commit, commit_entity(&commit).unwrap()).unwrap(),
+            vec![first_commit]
+        );
+
+        let mut expected = a;
+        expected.union(b);
+        let latest = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        assert_eq!(latest.tribles(), expected);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_removal_stays_removed() {
+        let dir = std::env::temp_dir().join(format!("tribles-workspace-{}", hex::encode(ufoid())));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo: FileRepo<Blake3> = FileRepo::open(dir.join("repo")).unwrap();
+        let head: FileHead<Blake3> = FileHead::open(dir.join("head")).unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+
+        let a = notes::entity!({ text: "a".try_into().unwrap() });
+        let b = notes::entity!({ text: "b".try_into().unwrap() });
+        let c = notes::entity!({ text: "c".try_into().unwrap() });
+        let mut setup = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        setup.add(a.clone());
+        setup.add(b.clone());
+        block_on(setup.commit(&key, None, 1)).unwrap();
+
+        let mut first = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        let mut second = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        first.remove(a.clone());
+        second.remove(b.clone());
+        second.add(c.clone());
+
+        block_on(first.commit(&key, None, 1)).unwrap();
+        block_on(second.commit(&key, None, 2)).unwrap();
+
+        let latest = block_on(Workspace::checkout(&repo, &head)).unwrap();
+        assert_eq!(latest.tribles(), c);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
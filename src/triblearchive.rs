@@ -1,5 +1,9 @@
+pub mod compressedarchive;
+pub mod deltaarchive;
 pub mod simplearchive;
 pub mod succinctarchive;
 
+pub use compressedarchive::CompressedArchive;
+pub use deltaarchive::DeltaArchive;
 pub use simplearchive::SimpleArchive;
 pub use succinctarchive::SuccinctArchive;
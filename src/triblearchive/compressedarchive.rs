@@ -0,0 +1,287 @@
+use anybytes::Bytes;
+use digest::{typenum::U32, Digest};
+
+use crate::{
+    trible::{A_END, A_START, E_END, E_START, TRIBLE_LEN},
+    types::Hash,
+    BlobParseError, Bloblike, Handle, TribleSet,
+};
+
+use super::SimpleArchive;
+
+/// The zstd level of [CompressedArchive::to_zstd].
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// A blob of tribles sorted in EAV order, where each trible only stores
+/// the bytes that differ from the trible before it.
+///
+/// Every trible is encoded as the length of the prefix it shares with the
+/// previous trible, or with all zeroes for the first one, followed by the
+/// remaining bytes. Only the longest shared prefix is accepted, so there is
+/// exactly one encoding for every set and equal sets have equal handles.
+pub struct CompressedArchive {
+    blob: Bytes,
+    len: usize,
+}
+
+impl CompressedArchive {
+    fn encode<'a, I>(tribles: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8; TRIBLE_LEN]>,
+    {
+        let mut blob = Vec::new();
+        let mut len = 0;
+        let mut prev = [0; TRIBLE_LEN];
+        for trible in tribles {
+            let shared = prev.iter().zip(trible).take_while(|(a, b)| a == b).count();
+            blob.push(shared as u8);
+            blob.extend_from_slice(&trible[shared..]);
+            prev = *trible;
+            len += 1;
+        }
+        CompressedArchive {
+            blob: blob.into(),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Compresses the blob with zstd for storage or transport.
+    ///
+    /// The result isn't canonical, it depends on the zstd version,
+    /// so archives are always addressed by the handle of their
+    /// uncompressed blob, which [CompressedArchive::from_zstd] restores.
+    #[cfg(feature = "zstd")]
+    pub fn to_zstd(&self) -> Bytes {
+        zstd::bulk::compress(&self.blob, ZSTD_LEVEL)
+            .expect("compressing into memory can't fail")
+            .into()
+    }
+
+    /// Decompresses a blob produced by [CompressedArchive::to_zstd],
+    /// giving up once it would grow beyond `max_len` bytes.
+    #[cfg(feature = "zstd")]
+    pub fn from_zstd(compressed: &[u8], max_len: usize) -> Result<Self, BlobParseError> {
+        use std::io::Read;
+
+        let decoder = zstd::stream::read::Decoder::new(compressed)
+            .map_err(|_| BlobParseError::Deserialize)?;
+        let mut blob = Vec::new();
+        decoder
+            .take(max_len as u64 + 1)
+            .read_to_end(&mut blob)
+            .map_err(|_| BlobParseError::Deserialize)?;
+        if blob.len() > max_len {
+            return Err(BlobParseError::BadLength(blob.len()));
+        }
+        CompressedArchive::from_blob(blob.into())
+    }
+
+    /// Decodes the tribles in EAV order.
+    pub fn iter(&self) -> impl Iterator<Item = [u8; TRIBLE_LEN]> + '_ {
+        let mut rest = &self.blob[..];
+        let mut trible = [0; TRIBLE_LEN];
+        std::iter::from_fn(move || {
+            let (&shared, tail) = rest.split_first()?;
+            let (suffix, tail) = tail.split_at(TRIBLE_LEN - shared as usize);
+            trible[shared as usize..].copy_from_slice(suffix);
+            rest = tail;
+            Some(trible)
+        })
+    }
+}
+
+impl Bloblike for CompressedArchive {
+    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
+        let mut rest = &blob[..];
+        let mut prev = [0; TRIBLE_LEN];
+        let mut len = 0;
+        while let Some((&shared, tail)) = rest.split_first() {
+            let shared = shared as usize;
+            if shared == TRIBLE_LEN && len > 0 {
+                return Err(BlobParseError::RedundantTrible);
+            }
+            if shared >= TRIBLE_LEN {
+                return Err(BlobParseError::NonCanonical);
+            }
+            if tail.len() < TRIBLE_LEN - shared {
+                return Err(BlobParseError::BadLength(blob.len()));
+            }
+            let (suffix, tail) = tail.split_at(TRIBLE_LEN - shared);
+            if suffix[0] == prev[shared] {
+                return Err(BlobParseError::NonCanonical);
+            }
+            if suffix[0] < prev[shared] {
+                return Err(BlobParseError::UnsortedTribles);
+            }
+            prev[shared..].copy_from_slice(suffix);
+            if prev[E_START..=E_END] == [0; 16] || prev[A_START..=A_END] == [0; 16] {
+                return Err(BlobParseError::NullId);
+            }
+            rest = tail;
+            len += 1;
+        }
+
+        Ok(CompressedArchive { blob, len })
+    }
+
+    fn into_blob(self) -> Bytes {
+        self.blob
+    }
+
+    fn as_handle<H>(&self) -> Handle<H, Self>
+    where
+        H: Digest<OutputSize = U32>,
+    {
+        let digest = H::digest(&self.blob);
+        unsafe { Handle::new(Hash::new(digest.into())) }
+    }
+}
+
+impl From<&TribleSet> for CompressedArchive {
+    fn from(set: &TribleSet) -> Self {
+        let tribles: Vec<[u8; TRIBLE_LEN]> =
+            set.eav.iter_prefix::<TRIBLE_LEN>().map(|p| p.0).collect();
+        CompressedArchive::encode(&tribles)
+    }
+}
+
+impl From<&SimpleArchive> for CompressedArchive {
+    fn from(archive: &SimpleArchive) -> Self {
+        CompressedArchive::encode(archive.iter())
+    }
+}
+
+impl From<&CompressedArchive> for TribleSet {
+    fn from(archive: &CompressedArchive) -> Self {
+        let tribles: Vec<[u8; TRIBLE_LEN]> = archive.iter().collect();
+        TribleSet::from_sorted(&tribles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::ufoid;
+    use crate::trible::Trible;
+    #[cfg(feature = "zstd")]
+    use crate::types::hash::Blake3;
+    use crate::{id_into_value, Id, NS};
+    use proptest::prelude::*;
+
+    NS! {
+        pub namespace knights {
+            "11716E486995A96A9388B672ABC55168" as loves: Id;
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(entries in prop::collection::vec((1u8..4, 1u8..4, 0u8..4), 0..64)) {
+            let set: TribleSet = entries
+                .into_iter()
+                .map(|(e, a, v)| {
+                    Trible::new_raw_values(id_into_value([e; 16]), id_into_value([a; 16]), [v; 32])
+                })
+                .collect();
+            let archive = CompressedArchive::from(&set);
+            prop_assert_eq!(archive.len(), set.len());
+            prop_assert_eq!(
+                &CompressedArchive::from(&SimpleArchive::from(&set)).blob,
+                &archive.blob
+            );
+
+            let archive = CompressedArchive::from_blob(archive.into_blob()).unwrap();
+            prop_assert_eq!(TribleSet::from(&archive), set);
+        }
+    }
+
+    #[test]
+    fn shares_prefixes() {
+        let mut set = TribleSet::new();
+        for _ in 0..16 {
+            let lover = ufoid();
+            for _ in 0..16 {
+                set.union(knights::entity!(lover, { loves: ufoid() }));
+            }
+        }
+        let simple = SimpleArchive::from(&set).into_blob();
+        let compressed = CompressedArchive::from(&set).into_blob();
+        assert!(compressed.len() * 3 < simple.len() * 2);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let trible = |e: u8, a: u8| {
+            let mut trible = [e; TRIBLE_LEN];
+            trible[A_START..=A_END].fill(a);
+            trible
+        };
+        let encode = |tribles: &[[u8; TRIBLE_LEN]]| CompressedArchive::encode(tribles).into_blob();
+
+        let blob = encode(&[trible(1, 1)]);
+        assert_eq!(
+            CompressedArchive::from_blob(blob.slice(..10)).err(),
+            Some(BlobParseError::BadLength(10))
+        );
+
+        // Not the longest shared prefix.
+        let mut blob = encode(&[trible(1, 1), trible(1, 2)]).to_vec();
+        blob[TRIBLE_LEN + 1] = 0;
+        blob.splice(TRIBLE_LEN + 2..TRIBLE_LEN + 2, [1; 16]);
+        assert_eq!(
+            CompressedArchive::from_blob(blob.into()).err(),
+            Some(BlobParseError::NonCanonical)
+        );
+
+        assert_eq!(
+            CompressedArchive::from_blob(encode(&[trible(2, 1), trible(1, 1)])).err(),
+            Some(BlobParseError::UnsortedTribles)
+        );
+        assert_eq!(
+            CompressedArchive::from_blob(encode(&[trible(1, 1), trible(1, 1)])).err(),
+            Some(BlobParseError::RedundantTrible)
+        );
+        assert_eq!(
+            CompressedArchive::from_blob(encode(&[trible(1, 0)])).err(),
+            Some(BlobParseError::NullId)
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_roundtrip() {
+        let mut set = TribleSet::new();
+        for _ in 0..16 {
+            set.union(knights::entity!({ loves: ufoid() }));
+        }
+        let archive = CompressedArchive::from(&set);
+        let compressed = archive.to_zstd();
+        assert!(compressed.len() < archive.blob.len());
+
+        let len = archive.blob.len();
+        let restored = CompressedArchive::from_zstd(&compressed, len).unwrap();
+        assert_eq!(
+            restored.as_handle::<Blake3>(),
+            archive.as_handle::<Blake3>()
+        );
+        assert_eq!(TribleSet::from(&restored), set);
+
+        assert_eq!(
+            CompressedArchive::from_zstd(&compressed, len - 1).err(),
+            Some(BlobParseError::BadLength(len))
+        );
+        assert_eq!(
+            CompressedArchive::from_zstd(b"not zstd", len).err(),
+            Some(BlobParseError::Deserialize)
+        );
+    }
+}
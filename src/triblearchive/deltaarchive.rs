@@ -0,0 +1,191 @@
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
+use anybytes::Bytes;
+use digest::{typenum::U32, Digest};
+use itertools::{EitherOrBoth, Itertools};
+
+use crate::{
+    trible::TRIBLE_LEN, types::Hash, BlobParseError, Bloblike, Handle, TribleSet, Value, VALUE_LEN,
+};
+
+use super::SimpleArchive;
+
+const COUNT_LEN: usize = 8;
+const HEADER_LEN: usize = VALUE_LEN + COUNT_LEN;
+
+/// The changes that turn the tribles of a base [SimpleArchive] into another set.
+///
+/// The blob starts with the hash of the base archive and the little endian
+/// number of added tribles, followed by the added and then the retracted tribles,
+/// each sorted in EAV order like those of a [SimpleArchive].
+/// No trible is both added and retracted.
+///
+/// Commits still reference a full [SimpleArchive] as their content,
+/// storing them as deltas is out of scope of this type, which only
+/// encodes and applies the changes between two archives.
+pub struct DeltaArchive<H> {
+    blob: Bytes,
+    added: usize,
+    _hasher: PhantomData<H>,
+}
+
+impl<H> DeltaArchive<H>
+where
+    H: Digest<OutputSize = U32>,
+{
+    /// Computes the changes from the tribles of `base` to `target`.
+    pub fn diff(base: &SimpleArchive, target: &TribleSet) -> Self {
+        let base_handle: Handle<H, SimpleArchive> = base.as_handle();
+        let base = TribleSet::from(base);
+        let added = target.difference(&base);
+        let retracted = base.difference(target);
+
+        let mut blob =
+            Vec::with_capacity(HEADER_LEN + (added.len() + retracted.len()) * TRIBLE_LEN);
+        blob.extend_from_slice(&base_handle.hash.bytes);
+        blob.extend_from_slice(&(added.len() as u64).to_le_bytes());
+        for (trible, _) in added.eav.iter_prefix::<TRIBLE_LEN>() {
+            blob.extend_from_slice(&trible);
+        }
+        for (trible, _) in retracted.eav.iter_prefix::<TRIBLE_LEN>() {
+            blob.extend_from_slice(&trible);
+        }
+        DeltaArchive {
+            blob: blob.into(),
+            added: added.len(),
+            _hasher: PhantomData,
+        }
+    }
+
+    /// The archive these changes apply to.
+    pub fn base(&self) -> Handle<H, SimpleArchive> {
+        let hash: Value = self.blob[..VALUE_LEN].try_into().unwrap();
+        unsafe { Handle::new(Hash::new(hash)) }
+    }
+
+    pub fn added(&self) -> impl Iterator<Item = &[u8; TRIBLE_LEN]> + '_ {
+        tribles(&self.blob[HEADER_LEN..HEADER_LEN + self.added * TRIBLE_LEN])
+    }
+
+    pub fn retracted(&self) -> impl Iterator<Item = &[u8; TRIBLE_LEN]> + '_ {
+        tribles(&self.blob[HEADER_LEN + self.added * TRIBLE_LEN..])
+    }
+
+    /// Applies the changes to `base`, which has to contain
+    /// the tribles of the [DeltaArchive::base] archive.
+    pub fn apply(&self, base: &TribleSet) -> TribleSet {
+        let mut tribles = base.clone();
+        for trible in self.retracted() {
+            tribles.remove_raw(trible);
+        }
+        tribles.union(TribleSet::from_sorted(self.added()));
+        tribles
+    }
+}
+
+fn tribles(bytes: &[u8]) -> impl Iterator<Item = &[u8; TRIBLE_LEN]> {
+    bytes
+        .chunks_exact(TRIBLE_LEN)
+        .map(|trible| trible.try_into().unwrap())
+}
+
+impl<H> Bloblike for DeltaArchive<H> {
+    fn into_blob(self) -> Bytes {
+        self.blob
+    }
+
+    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
+        if blob.len() < HEADER_LEN || (blob.len() - HEADER_LEN) % TRIBLE_LEN != 0 {
+            return Err(BlobParseError::BadLength(blob.len()));
+        }
+        let count: [u8; COUNT_LEN] = blob[VALUE_LEN..HEADER_LEN].try_into().unwrap();
+        let added = u64::from_le_bytes(count);
+        let len = (blob.len() - HEADER_LEN) / TRIBLE_LEN;
+        if added > len as u64 {
+            return Err(BlobParseError::BadLength(blob.len()));
+        }
+        let added = added as usize;
+        let (added_tribles, retracted_tribles) = blob[HEADER_LEN..].split_at(added * TRIBLE_LEN);
+        SimpleArchive::validate(added_tribles)?;
+        SimpleArchive::validate(retracted_tribles)?;
+        // Both lists are sorted, so shared tribles line up in a merge.
+        if tribles(added_tribles)
+            .merge_join_by(tribles(retracted_tribles), |a, r| a.cmp(r))
+            .any(|pair| matches!(pair, EitherOrBoth::Both(..)))
+        {
+            return Err(BlobParseError::NonCanonical);
+        }
+        Ok(DeltaArchive {
+            blob,
+            added,
+            _hasher: PhantomData,
+        })
+    }
+
+    fn as_handle<H2>(&self) -> Handle<H2, Self>
+    where
+        H2: Digest<OutputSize = U32>,
+    {
+        let digest = H2::digest(&self.blob);
+        unsafe { Handle::new(Hash::new(digest.into())) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::hash::Blake3;
+    use crate::{trible::Trible, ufoid, BlobSet};
+
+    #[test]
+    fn diff_and_apply() {
+        let ids: Vec<_> = (0..8).map(|_| ufoid()).collect();
+        let base: TribleSet = (0..6)
+            .map(|i| Trible::new(ids[i], ids[7], ids[i + 1]))
+            .collect();
+        let mut target = base.clone();
+        target.remove(&Trible::new(ids[0], ids[7], ids[1]));
+        target.remove(&Trible::new(ids[3], ids[7], ids[4]));
+        target.insert(&Trible::new(ids[6], ids[7], ids[0]));
+
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let base_handle = blobs.put(SimpleArchive::from(&base));
+        let delta: DeltaArchive<Blake3> = DeltaArchive::diff(&SimpleArchive::from(&base), &target);
+        assert_eq!(delta.added().count(), 1);
+        assert_eq!(delta.retracted().count(), 2);
+
+        let delta: DeltaArchive<Blake3> = DeltaArchive::from_blob(delta.into_blob()).unwrap();
+        assert_eq!(delta.base(), base_handle);
+        assert_eq!(delta.apply(&base), target);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut blob = vec![0u8; HEADER_LEN + TRIBLE_LEN];
+        blob[VALUE_LEN] = 2;
+        assert_eq!(
+            DeltaArchive::<Blake3>::from_blob(blob.clone().into()).err(),
+            Some(BlobParseError::BadLength(blob.len()))
+        );
+
+        blob[VALUE_LEN] = 1;
+        assert_eq!(
+            DeltaArchive::<Blake3>::from_blob(blob.into()).err(),
+            Some(BlobParseError::NullId)
+        );
+    }
+
+    #[test]
+    fn added_and_retracted() {
+        let trible = Trible::new(ufoid(), ufoid(), ufoid());
+        let mut blob = vec![0u8; HEADER_LEN];
+        blob[VALUE_LEN] = 1;
+        blob.extend_from_slice(&trible.data);
+        blob.extend_from_slice(&trible.data);
+        assert_eq!(
+            DeltaArchive::<Blake3>::from_blob(blob.into()).err(),
+            Some(BlobParseError::NonCanonical)
+        );
+    }
+}
@@ -1,64 +1,264 @@
+mod simplearchiveconstraint;
+
 use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
 use digest::{typenum::U32, Digest};
 use anybytes::Bytes;
+use simplearchiveconstraint::*;
 
 use crate::{
-    trible::{A_END, A_START, E_END, E_START, TRIBLE_LEN},
+    patch::KeyOrdering,
+    query::TriblePattern,
+    trible::{
+        AEVOrder, AVEOrder, EAVOrder, EVAOrder, VAEOrder, VEAOrder, A_END, A_START, E_END, E_START,
+        TRIBLE_LEN,
+    },
     types::Hash,
-    BlobParseError, Bloblike, Handle, TribleSet,
+    BlobParseError, Bloblike, Handle, Id, TribleSet, Valuelike,
 };
 
-pub struct SimpleArchive(Bytes);
+/// A blob of tribles sorted in EAV order.
+///
+/// Archives can be queried directly via [TriblePattern], which
+/// uses the blob itself for the EAV order and builds the other orders
+/// on first use as permutations of the tribles.
+pub struct SimpleArchive {
+    tribles: Bytes,
+    eva: OnceLock<Box<[u32]>>,
+    aev: OnceLock<Box<[u32]>>,
+    ave: OnceLock<Box<[u32]>>,
+    vea: OnceLock<Box<[u32]>>,
+    vae: OnceLock<Box<[u32]>>,
+}
 
-impl Bloblike for SimpleArchive {
-    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
-        let len: usize = blob.len();
+#[derive(Debug)]
+pub enum MmapErr {
+    IO(io::Error),
+    Parse(BlobParseError),
+}
+
+impl fmt::Display for MmapErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "failed to map archive: {}", e),
+            Self::Parse(e) => write!(f, "failed to map archive: {}", e),
+        }
+    }
+}
+
+impl Error for MmapErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            Self::Parse(e) => Some(e),
+        }
+    }
+}
 
-        if len % TRIBLE_LEN != 0 {
-            return Err(BlobParseError::new(
-                "simple archive must be multiples of 64bytes long",
-            ));
+impl SimpleArchive {
+    fn new(tribles: Bytes) -> Self {
+        SimpleArchive {
+            tribles,
+            eva: OnceLock::new(),
+            aev: OnceLock::new(),
+            ave: OnceLock::new(),
+            vea: OnceLock::new(),
+            vae: OnceLock::new(),
         }
+    }
+
+    /// Maps the archive stored at `path` into memory without copying it.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified while the archive, or any blob sliced
+    /// from it, is alive. Blobs stored in a [FileRepo](crate::remote::filestore::FileRepo)
+    /// are never modified after they have been written.
+    pub unsafe fn mmap<P: AsRef<Path>>(path: P) -> Result<Self, MmapErr> {
+        let file = File::open(path).map_err(MmapErr::IO)?;
+        let map = memmap2::Mmap::map(&file).map_err(MmapErr::IO)?;
+        Self::from_blob(Bytes::from_owner(map)).map_err(MmapErr::Parse)
+    }
 
-        let mut prev_trible = None;
+    /// Checks that `blob` is the canonical archive of its tribles,
+    /// i.e. the one produced from the [TribleSet] containing them.
+    ///
+    /// This has to hold for every blob from an untrusted source, because
+    /// a second encoding of the same set would have a different handle.
+    pub fn validate(blob: &[u8]) -> Result<(), BlobParseError> {
+        if blob.len() % TRIBLE_LEN != 0 {
+            return Err(BlobParseError::BadLength(blob.len()));
+        }
+
+        let mut prev_trible: Option<&[u8]> = None;
         for trible in blob.chunks_exact(TRIBLE_LEN) {
-            let t: &[u8; 64] = trible.try_into().unwrap();
-            if t[E_START..=E_END] == [0; 16] {
-                return Err(BlobParseError::new(
-                    "validation error: trible contains NULL id in E position",
-                ));
-            }
-            if t[A_START..=A_END] == [0; 16] {
-                return Err(BlobParseError::new(
-                    "validation error: trible contains NULL id in A position",
-                ));
+            if trible[E_START..=E_END] == [0; 16] || trible[A_START..=A_END] == [0; 16] {
+                return Err(BlobParseError::NullId);
             }
             if let Some(prev) = prev_trible {
-                if prev == t {
-                    return Err(BlobParseError::new("validation error: redundant trible"));
+                if prev == trible {
+                    return Err(BlobParseError::RedundantTrible);
                 }
-                if prev > t {
-                    return Err(BlobParseError::new(
-                        "validation error: tribles must be sorted in ascending order",
-                    ));
+                if prev > trible {
+                    return Err(BlobParseError::UnsortedTribles);
                 }
+            }
+            prev_trible = Some(trible);
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tribles.len() / TRIBLE_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tribles.is_empty()
+    }
+
+    /// Iterates over the tribles in EAV order.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8; TRIBLE_LEN]> + '_ {
+        self.tribles
+            .chunks_exact(TRIBLE_LEN)
+            .map(|trible| trible.try_into().unwrap())
+    }
+
+    fn trible(&self, index: usize) -> &[u8; TRIBLE_LEN] {
+        self.tribles[index * TRIBLE_LEN..(index + 1) * TRIBLE_LEN]
+            .try_into()
+            .unwrap()
+    }
+
+    fn eav(&self) -> Ordered<'_, EAVOrder> {
+        Ordered::new(self, None)
+    }
+
+    fn eva(&self) -> Ordered<'_, EVAOrder> {
+        self.ordered(&self.eva)
+    }
+
+    fn aev(&self) -> Ordered<'_, AEVOrder> {
+        self.ordered(&self.aev)
+    }
+
+    fn ave(&self) -> Ordered<'_, AVEOrder> {
+        self.ordered(&self.ave)
+    }
+
+    fn vea(&self) -> Ordered<'_, VEAOrder> {
+        self.ordered(&self.vea)
+    }
+
+    fn vae(&self) -> Ordered<'_, VAEOrder> {
+        self.ordered(&self.vae)
+    }
+
+    fn ordered<'a, O>(&'a self, permutation: &'a OnceLock<Box<[u32]>>) -> Ordered<'a, O>
+    where
+        O: KeyOrdering<TRIBLE_LEN>,
+    {
+        let permutation = permutation.get_or_init(|| {
+            let len: u32 = self.len().try_into().expect("archive too large to index");
+            let mut permutation: Vec<u32> = (0..len).collect();
+            permutation.sort_by_cached_key(|&index| O::tree_ordered(self.trible(index as usize)));
+            permutation.into()
+        });
+        Ordered::new(self, Some(permutation))
+    }
+}
+
+/// The tribles of an archive viewed in the order `O`,
+/// either directly or through a sorting permutation.
+struct Ordered<'a, O> {
+    archive: &'a SimpleArchive,
+    permutation: Option<&'a [u32]>,
+    _order: PhantomData<O>,
+}
+
+impl<'a, O> Ordered<'a, O>
+where
+    O: KeyOrdering<TRIBLE_LEN>,
+{
+    fn new(archive: &'a SimpleArchive, permutation: Option<&'a [u32]>) -> Self {
+        Ordered {
+            archive,
+            permutation,
+            _order: PhantomData,
+        }
+    }
+
+    fn key(&self, index: usize) -> [u8; TRIBLE_LEN] {
+        let index = self.permutation.map_or(index, |p| p[index] as usize);
+        O::tree_ordered(self.archive.trible(index))
+    }
 
-                prev_trible = Some(t);
+    /// Returns the first index in `start..end` for which `pred` is false,
+    /// where `pred` must be true for a prefix of the range.
+    fn partition_point<F>(&self, mut start: usize, mut end: usize, pred: F) -> usize
+    where
+        F: Fn(&[u8; TRIBLE_LEN]) -> bool,
+    {
+        while start < end {
+            let mid = start + (end - start) / 2;
+            if pred(&self.key(mid)) {
+                start = mid + 1;
+            } else {
+                end = mid;
             }
         }
+        start
+    }
+
+    /// The indices of the keys that start with `prefix`.
+    fn range(&self, prefix: &[u8]) -> Range<usize> {
+        let len = self.archive.len();
+        let start = self.partition_point(0, len, |key| key[..prefix.len()] < *prefix);
+        let end = self.partition_point(start, len, |key| key[..prefix.len()] <= *prefix);
+        start..end
+    }
+
+    fn has_prefix(&self, prefix: &[u8]) -> bool {
+        !self.range(prefix).is_empty()
+    }
+
+    /// Calls `f` with every distinct infix that follows `prefix`, in order.
+    fn infixes<const INFIX_LEN: usize, F>(&self, prefix: &[u8], mut f: F)
+    where
+        F: FnMut([u8; INFIX_LEN]),
+    {
+        let range = self.range(prefix);
+        let end = prefix.len() + INFIX_LEN;
+        let mut index = range.start;
+        while index < range.end {
+            let key = self.key(index);
+            f(key[prefix.len()..end].try_into().unwrap());
+            index = self.partition_point(index, range.end, |other| other[..end] <= key[..end]);
+        }
+    }
+}
 
-        Ok(SimpleArchive(blob))
+impl Bloblike for SimpleArchive {
+    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
+        SimpleArchive::validate(&blob)?;
+        Ok(SimpleArchive::new(blob))
     }
 
     fn into_blob(self) -> Bytes {
-        self.0
+        self.tribles
     }
 
     fn as_handle<H>(&self) -> Handle<H, Self>
     where
         H: Digest<OutputSize = U32>,
     {
-        let digest = H::digest(&self.0);
+        let digest = H::digest(&self.tribles);
         unsafe { Handle::new(Hash::new(digest.into())) }
     }
 }
@@ -68,16 +268,187 @@ impl From<&TribleSet> for SimpleArchive {
         let mut tribles: Vec<[u8; 64]> = Vec::with_capacity(set.len());
         tribles.extend(set.eav.iter_prefix::<64>().map(|p| p.0));
         let buffer: Vec<u8> = bytemuck::allocation::cast_vec(tribles);
-        SimpleArchive(buffer.into())
+        SimpleArchive::new(buffer.into())
     }
 }
 
 impl From<&SimpleArchive> for TribleSet {
     fn from(archive: &SimpleArchive) -> Self {
-        let mut tribles = TribleSet::new();
-        for t in archive.0.chunks_exact(TRIBLE_LEN) {
-            tribles.insert_raw(t.try_into().unwrap());
+        TribleSet::from_sorted(archive.iter())
+    }
+}
+
+impl TriblePattern for SimpleArchive {
+    type PatternConstraint<'a, V>
+     = SimpleArchiveConstraint<'a, V>
+     where V: Valuelike;
+
+    fn pattern<'a, V>(
+        &'a self,
+        e: crate::query::Variable<Id>,
+        a: crate::query::Variable<Id>,
+        v: crate::query::Variable<V>,
+    ) -> Self::PatternConstraint<'a, V>
+    where
+        V: Valuelike,
+    {
+        SimpleArchiveConstraint::new(e, a, v, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::ufoid;
+    use crate::query::find;
+    use crate::trible::Trible;
+    use crate::types::ShortString;
+    use crate::{and, id_into_value, Value, NS};
+    use arbitrary::{Arbitrary, Unstructured};
+    use proptest::prelude::*;
+
+    NS! {
+        pub namespace knights {
+            "C2F8D6226A35FB7EB4D57ECDBE715404" as loves: Id;
+            "45DF8057982D88709D7E58B24F274707" as name: ShortString;
+        }
+    }
+
+    /// Two entities with the same value for an attribute.
+    type SharedValue = (Id, Id, Id, Value);
+    /// Two attributes of the same entity.
+    type SharedEntity = (Id, Id, Value, Id, Value);
+
+    fn solutions<P: TriblePattern>(kb: &P) -> (Vec<SharedValue>, Vec<SharedEntity>) {
+        let mut shared_value: Vec<SharedValue> = find!(
+            ctx,
+            (e, f, a, v),
+            and!(kb.pattern(e, a, v), kb.pattern(f, a, v))
+        )
+        .map(Result::unwrap)
+        .collect();
+        shared_value.sort();
+        let mut shared_entity: Vec<SharedEntity> = find!(
+            ctx,
+            (e, a, v, b, w),
+            and!(kb.pattern(e, a, v), kb.pattern(e, b, w))
+        )
+        .map(Result::unwrap)
+        .collect();
+        shared_entity.sort();
+        (shared_value, shared_entity)
+    }
+
+    proptest! {
+        #[test]
+        fn pattern_matches_tribleset(entries in prop::collection::vec((1u8..4, 1u8..4, 0u8..4), 1..64)) {
+            let set: TribleSet = entries
+                .into_iter()
+                .map(|(e, a, v)| {
+                    Trible::new_raw_values(id_into_value([e; 16]), id_into_value([a; 16]), [v; 32])
+                })
+                .collect();
+            let archive = SimpleArchive::from(&set);
+            prop_assert_eq!(solutions(&archive), solutions(&set));
+        }
+    }
+
+    #[test]
+    fn archive_pattern() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+        kb.union(knights::entity!(juliet, {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo
+        }));
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet
+        }));
+
+        let dir = std::env::temp_dir().join(format!("tribles-archive-{}", hex::encode(ufoid())));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("archive");
+        std::fs::write(&path, SimpleArchive::from(&kb).into_blob()).unwrap();
+        let archive = unsafe { SimpleArchive::mmap(&path) }.unwrap();
+        assert_eq!(archive.len(), kb.len());
+
+        let r: Vec<_> = find!(
+            ctx,
+            (juliet, name),
+            knights::pattern!(ctx, archive, [
+            {name: ("Romeo".try_into().unwrap()),
+             loves: juliet},
+            {juliet @
+                name: name
+            }])
+        )
+        .collect();
+        assert_eq!(vec![Ok((juliet, "Juliet".try_into().unwrap(),))], r);
+
+        drop(archive);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut tribles = vec![1u8; TRIBLE_LEN];
+        assert_eq!(
+            SimpleArchive::from_blob(tribles[..10].to_vec().into()).err(),
+            Some(BlobParseError::BadLength(10))
+        );
+
+        let mut redundant = tribles.clone();
+        redundant.extend_from_within(..);
+        assert_eq!(
+            SimpleArchive::from_blob(redundant.into()).err(),
+            Some(BlobParseError::RedundantTrible)
+        );
+
+        let mut unsorted = vec![2u8; TRIBLE_LEN];
+        unsorted.extend_from_slice(&tribles);
+        assert_eq!(
+            SimpleArchive::from_blob(unsorted.into()).err(),
+            Some(BlobParseError::UnsortedTribles)
+        );
+
+        tribles[E_START..=E_END].fill(0);
+        assert_eq!(
+            SimpleArchive::from_blob(tribles.into()).err(),
+            Some(BlobParseError::NullId)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn canonical(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+            let tribles = Vec::<Trible>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            let set: TribleSet = tribles
+                .into_iter()
+                .filter(|t| t.data[E_START..=E_END] != [0; 16] && t.data[A_START..=A_END] != [0; 16])
+                .collect();
+            let blob = SimpleArchive::from(&set).into_blob();
+            let archive = SimpleArchive::from_blob(blob.clone()).unwrap();
+            prop_assert_eq!(TribleSet::from(&archive), set);
+
+            if blob.len() >= 2 * TRIBLE_LEN {
+                let mut swapped = blob.to_vec();
+                swapped[..2 * TRIBLE_LEN].rotate_left(TRIBLE_LEN);
+                prop_assert_eq!(
+                    SimpleArchive::validate(&swapped),
+                    Err(BlobParseError::UnsortedTribles)
+                );
+            }
+            if !blob.is_empty() {
+                let mut repeated = blob.to_vec();
+                repeated.splice(..0, blob[..TRIBLE_LEN].iter().copied());
+                prop_assert_eq!(
+                    SimpleArchive::validate(&repeated),
+                    Err(BlobParseError::RedundantTrible)
+                );
+            }
         }
-        tribles
     }
 }
@@ -0,0 +1,301 @@
+use super::*;
+use crate::id_from_value;
+use crate::id_into_value;
+use crate::query::*;
+use crate::Id;
+use crate::Value;
+use crate::ID_LEN;
+use crate::VALUE_LEN;
+
+/// Matches a trible pattern with binary searches over the orders of a [SimpleArchive].
+///
+/// The estimates count the matching tribles rather than the distinct values,
+/// so they are upper bounds of the number of proposals.
+pub struct SimpleArchiveConstraint<'a, V>
+where
+    V: Valuelike,
+{
+    variable_e: Variable<Id>,
+    variable_a: Variable<Id>,
+    variable_v: Variable<V>,
+    archive: &'a SimpleArchive,
+}
+
+impl<'a, V> SimpleArchiveConstraint<'a, V>
+where
+    V: Valuelike,
+{
+    pub fn new(
+        variable_e: Variable<Id>,
+        variable_a: Variable<Id>,
+        variable_v: Variable<V>,
+        archive: &'a SimpleArchive,
+    ) -> Self {
+        SimpleArchiveConstraint {
+            variable_e,
+            variable_a,
+            variable_v,
+            archive,
+        }
+    }
+}
+
+impl<'a, V> Constraint<'a> for SimpleArchiveConstraint<'a, V>
+where
+    V: Valuelike,
+{
+    fn variables(&self) -> VariableSet {
+        let mut variables = VariableSet::new_empty();
+        variables.set(self.variable_e.index);
+        variables.set(self.variable_a.index);
+        variables.set(self.variable_v.index);
+        variables
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        self.variable_e.index == variable
+            || self.variable_a.index == variable
+            || self.variable_v.index == variable
+    }
+
+    fn estimate(&self, variable: VariableId, binding: &Binding) -> usize {
+        let e_var = self.variable_e.index == variable;
+        let a_var = self.variable_a.index == variable;
+        let v_var = self.variable_v.index == variable;
+
+        let e_bound = binding.get(self.variable_e.index).map(id_from_value);
+        let a_bound = binding.get(self.variable_a.index).map(id_from_value);
+        let v_bound = binding.get(self.variable_v.index);
+
+        match (e_bound, a_bound, v_bound, e_var, a_var, v_var) {
+            (None, None, None, true, false, false) => self.archive.eav().range(&[]).len(),
+            (None, None, None, false, true, false) => self.archive.aev().range(&[]).len(),
+            (None, None, None, false, false, true) => self.archive.vea().range(&[]).len(),
+            (Some(e), None, None, false, true, false) => {
+                let mut prefix = [0u8; ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                self.archive.eav().range(&prefix).len()
+            }
+            (Some(e), None, None, false, false, true) => {
+                let mut prefix = [0u8; ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                self.archive.eva().range(&prefix).len()
+            }
+            (None, Some(a), None, true, false, false) => {
+                let mut prefix = [0u8; ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a[..]);
+                self.archive.aev().range(&prefix).len()
+            }
+            (None, Some(a), None, false, false, true) => {
+                let mut prefix = [0u8; ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a[..]);
+                self.archive.ave().range(&prefix).len()
+            }
+            (None, None, Some(v), true, false, false) => {
+                let mut prefix = [0u8; VALUE_LEN];
+                prefix[0..VALUE_LEN].copy_from_slice(&v[..]);
+                self.archive.vea().range(&prefix).len()
+            }
+            (None, None, Some(v), false, true, false) => {
+                let mut prefix = [0u8; VALUE_LEN];
+                prefix[0..VALUE_LEN].copy_from_slice(&v[..]);
+                self.archive.vae().range(&prefix).len()
+            }
+            (None, Some(a), Some(v), true, false, false) => {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v);
+                self.archive.ave().range(&prefix).len()
+            }
+            (Some(e), None, Some(v), false, true, false) => {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v);
+                self.archive.eva().range(&prefix).len()
+            }
+            (Some(e), Some(a), None, false, false, true) => {
+                let mut prefix = [0u8; ID_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e);
+                prefix[ID_LEN..ID_LEN + ID_LEN].copy_from_slice(&a);
+                self.archive.eav().range(&prefix).len()
+            }
+            _ => panic!(),
+        }
+    }
+
+    fn propose(&self, variable: VariableId, binding: &Binding) -> Vec<Value> {
+        let e_var = self.variable_e.index == variable;
+        let a_var = self.variable_a.index == variable;
+        let v_var = self.variable_v.index == variable;
+
+        let e_bound = binding.get(self.variable_e.index).map(id_from_value);
+        let a_bound = binding.get(self.variable_a.index).map(id_from_value);
+        let v_bound = binding.get(self.variable_v.index);
+
+        match (e_bound, a_bound, v_bound, e_var, a_var, v_var) {
+            (None, None, None, true, false, false) => {
+                let mut r = vec![];
+                self.archive
+                    .eav()
+                    .infixes(&[], |e| r.push(id_into_value(e)));
+                r
+            }
+            (None, None, None, false, true, false) => {
+                let mut r = vec![];
+                self.archive
+                    .aev()
+                    .infixes(&[], |a| r.push(id_into_value(a)));
+                r
+            }
+            (None, None, None, false, false, true) => {
+                let mut r = vec![];
+                self.archive.vea().infixes(&[], |v| r.push(v));
+                r
+            }
+
+            (Some(e), None, None, false, true, false) => {
+                let mut r = vec![];
+                self.archive.eav().infixes(&e, |a| r.push(id_into_value(a)));
+                r
+            }
+            (Some(e), None, None, false, false, true) => {
+                let mut r = vec![];
+                self.archive.eva().infixes(&e, |v| r.push(v));
+                r
+            }
+
+            (None, Some(a), None, true, false, false) => {
+                let mut r = vec![];
+                self.archive.aev().infixes(&a, |e| r.push(id_into_value(e)));
+                r
+            }
+            (None, Some(a), None, false, false, true) => {
+                let mut r = vec![];
+                self.archive.ave().infixes(&a, |v| r.push(v));
+                r
+            }
+
+            (None, None, Some(v), true, false, false) => {
+                let mut r = vec![];
+                self.archive.vea().infixes(&v, |e| r.push(id_into_value(e)));
+                r
+            }
+            (None, None, Some(v), false, true, false) => {
+                let mut r = vec![];
+                self.archive.vae().infixes(&v, |a| r.push(id_into_value(a)));
+                r
+            }
+            (None, Some(a), Some(v), true, false, false) => {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a[..]);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v[..]);
+                let mut r = vec![];
+                self.archive
+                    .ave()
+                    .infixes(&prefix, |e| r.push(id_into_value(e)));
+                r
+            }
+            (Some(e), None, Some(v), false, true, false) => {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v[..]);
+                let mut r = vec![];
+                self.archive
+                    .eva()
+                    .infixes(&prefix, |a| r.push(id_into_value(a)));
+                r
+            }
+            (Some(e), Some(a), None, false, false, true) => {
+                let mut prefix = [0u8; ID_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                prefix[ID_LEN..ID_LEN + ID_LEN].copy_from_slice(&a[..]);
+                let mut r = vec![];
+                self.archive.eav().infixes(&prefix, |v| r.push(v));
+                r
+            }
+            _ => panic!(),
+        }
+    }
+
+    fn confirm(&self, variable: VariableId, binding: &Binding, proposals: &mut Vec<Value>) {
+        let e_var = self.variable_e.index == variable;
+        let a_var = self.variable_a.index == variable;
+        let v_var = self.variable_v.index == variable;
+
+        let e_bound = binding.get(self.variable_e.index).map(id_from_value);
+        let a_bound = binding.get(self.variable_a.index).map(id_from_value);
+        let v_bound = binding.get(self.variable_v.index);
+
+        match (e_bound, a_bound, v_bound, e_var, a_var, v_var) {
+            (None, None, None, true, false, false) => {
+                proposals.retain(|value| self.archive.eav().has_prefix(&id_from_value(*value)))
+            }
+            (None, None, None, false, true, false) => {
+                proposals.retain(|value| self.archive.aev().has_prefix(&id_from_value(*value)))
+            }
+            (None, None, None, false, false, true) => {
+                proposals.retain(|value| self.archive.vea().has_prefix(value))
+            }
+            (Some(e), None, None, false, true, false) => proposals.retain(|value| {
+                let mut prefix = [0u8; ID_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                prefix[ID_LEN..ID_LEN + ID_LEN].copy_from_slice(&id_from_value(*value));
+                self.archive.eav().has_prefix(&prefix)
+            }),
+            (Some(e), None, None, false, false, true) => proposals.retain(|value| {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e[..]);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(value);
+                self.archive.eva().has_prefix(&prefix)
+            }),
+            (None, Some(a), None, true, false, false) => proposals.retain(|value| {
+                let mut prefix = [0u8; ID_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a[..]);
+                prefix[ID_LEN..ID_LEN + ID_LEN].copy_from_slice(&id_from_value(*value));
+                self.archive.aev().has_prefix(&prefix)
+            }),
+            (None, Some(a), None, false, false, true) => proposals.retain(|value| {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a[..]);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(value);
+                self.archive.ave().has_prefix(&prefix)
+            }),
+            (None, None, Some(v), true, false, false) => proposals.retain(|value| {
+                let mut prefix = [0u8; VALUE_LEN + ID_LEN];
+                prefix[0..VALUE_LEN].copy_from_slice(&v[..]);
+                prefix[VALUE_LEN..VALUE_LEN + ID_LEN].copy_from_slice(&id_from_value(*value));
+                self.archive.vea().has_prefix(&prefix)
+            }),
+            (None, None, Some(v), false, true, false) => proposals.retain(|value| {
+                let mut prefix = [0u8; VALUE_LEN + ID_LEN];
+                prefix[0..VALUE_LEN].copy_from_slice(&v[..]);
+                prefix[VALUE_LEN..VALUE_LEN + ID_LEN].copy_from_slice(&id_from_value(*value));
+                self.archive.vae().has_prefix(&prefix)
+            }),
+            (None, Some(a), Some(v), true, false, false) => proposals.retain(|value: &[u8; 32]| {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&a);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v);
+                prefix[ID_LEN + VALUE_LEN..ID_LEN + VALUE_LEN + ID_LEN]
+                    .copy_from_slice(&id_from_value(*value));
+                self.archive.ave().has_prefix(&prefix)
+            }),
+            (Some(e), None, Some(v), false, true, false) => proposals.retain(|value: &[u8; 32]| {
+                let mut prefix = [0u8; ID_LEN + VALUE_LEN + ID_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e);
+                prefix[ID_LEN..ID_LEN + VALUE_LEN].copy_from_slice(&v);
+                prefix[ID_LEN + VALUE_LEN..ID_LEN + VALUE_LEN + ID_LEN]
+                    .copy_from_slice(&id_from_value(*value));
+                self.archive.eva().has_prefix(&prefix)
+            }),
+            (Some(e), Some(a), None, false, false, true) => proposals.retain(|value: &[u8; 32]| {
+                let mut prefix = [0u8; ID_LEN + ID_LEN + VALUE_LEN];
+                prefix[0..ID_LEN].copy_from_slice(&e);
+                prefix[ID_LEN..ID_LEN + ID_LEN].copy_from_slice(&a);
+                prefix[ID_LEN + ID_LEN..ID_LEN + ID_LEN + VALUE_LEN].copy_from_slice(value);
+                self.archive.eav().has_prefix(&prefix)
+            }),
+            _ => panic!("invalid trible constraint state"),
+        }
+    }
+}
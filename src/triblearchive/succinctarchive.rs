@@ -1,14 +1,19 @@
+mod layout;
 mod succinctarchiveconstraint;
+mod succinctarchiverangeconstraint;
 mod universe;
 
 use std::convert::TryInto;
+use std::io::{Read, Write};
 use std::iter;
 use succinctarchiveconstraint::*;
+use succinctarchiverangeconstraint::*;
 
-use crate::query::TriblePattern;
+use crate::query::{RangePattern, TriblePattern, ValueRange};
 use crate::trible::Trible;
+use crate::types::Hash;
 use crate::{id_into_value, Id, Valuelike};
-use crate::{Bloblike, Value};
+use crate::{BlobParseError, Bloblike, Bytes, Handle, Value};
 
 use itertools::Itertools;
 
@@ -17,11 +22,13 @@ use sucds::char_sequences::WaveletMatrix;
 use sucds::mii_sequences::{EliasFano, EliasFanoBuilder};
 
 use sucds::int_vectors::CompactVector;
+use sucds::Serializable;
 
 use digest::{typenum::U32, Digest};
 
 use crate::TribleSet;
 
+pub use layout::Layout;
 pub use universe::*;
 
 #[derive(Debug, Clone)]
@@ -251,24 +258,207 @@ where
     }
 }
 
-impl<U, B> Bloblike for SuccinctArchive<U, B>
+impl<U, B> RangePattern for SuccinctArchive<U, B>
+where
+    U: Universe,
+    B: Build + Access + Rank + Select + NumBits,
+{
+    type RangeConstraint<'a, V, R>
+     = SuccinctArchiveRangeConstraint<'a, V, R, U, B>
+     where V: Valuelike,
+           R: ValueRange + 'a,
+           U: 'a,
+           B: 'a;
+
+    fn value_range<'a, V, R>(
+        &'a self,
+        v: crate::query::Variable<V>,
+        range: R,
+    ) -> Self::RangeConstraint<'a, V, R>
+    where
+        V: Valuelike,
+        R: ValueRange + 'a,
+    {
+        SuccinctArchiveRangeConstraint::new(v, range, self)
+    }
+}
+
+/// Magic bytes at the start of every serialized [SuccinctArchive].
+const ARCHIVE_MAGIC: [u8; 8] = *b"TRBLSUCC";
+/// Version of the serialized layout, bump on incompatible changes.
+const ARCHIVE_VERSION: u64 = 1;
+
+/// The blob layout is the magic `TRBLSUCC`, a little endian `u64` version,
+/// followed by the domain, the `e_a`, `a_a` and `v_a` attribute indices
+/// and the `eav`, `vea`, `ave`, `vae`, `eva` and `aev` wavelet matrices,
+/// each in their respective `sucds` serialization.
+impl<U, B> Serializable for SuccinctArchive<U, B>
+where
+    U: Universe + Serializable,
+    B: Serializable,
+{
+    fn serialize_into<W: Write>(&self, mut writer: W) -> anyhow::Result<usize> {
+        writer.write_all(&ARCHIVE_MAGIC)?;
+        let mut mem = ARCHIVE_MAGIC.len();
+        mem += ARCHIVE_VERSION.serialize_into(&mut writer)?;
+        mem += self.domain.serialize_into(&mut writer)?;
+        mem += self.e_a.serialize_into(&mut writer)?;
+        mem += self.a_a.serialize_into(&mut writer)?;
+        mem += self.v_a.serialize_into(&mut writer)?;
+        mem += self.eav_c.serialize_into(&mut writer)?;
+        mem += self.vea_c.serialize_into(&mut writer)?;
+        mem += self.ave_c.serialize_into(&mut writer)?;
+        mem += self.vae_c.serialize_into(&mut writer)?;
+        mem += self.eva_c.serialize_into(&mut writer)?;
+        mem += self.aev_c.serialize_into(&mut writer)?;
+        Ok(mem)
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != ARCHIVE_MAGIC {
+            anyhow::bail!("not a succinct archive");
+        }
+        let version = u64::deserialize_from(&mut reader)?;
+        if version != ARCHIVE_VERSION {
+            anyhow::bail!("unsupported succinct archive version {}", version);
+        }
+        Ok(SuccinctArchive {
+            domain: U::deserialize_from(&mut reader)?,
+            e_a: EliasFano::deserialize_from(&mut reader)?,
+            a_a: EliasFano::deserialize_from(&mut reader)?,
+            v_a: EliasFano::deserialize_from(&mut reader)?,
+            eav_c: WaveletMatrix::deserialize_from(&mut reader)?,
+            vea_c: WaveletMatrix::deserialize_from(&mut reader)?,
+            ave_c: WaveletMatrix::deserialize_from(&mut reader)?,
+            vae_c: WaveletMatrix::deserialize_from(&mut reader)?,
+            eva_c: WaveletMatrix::deserialize_from(&mut reader)?,
+            aev_c: WaveletMatrix::deserialize_from(&mut reader)?,
+        })
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        ARCHIVE_MAGIC.len()
+            + ARCHIVE_VERSION.size_in_bytes()
+            + Serializable::size_in_bytes(&self.domain)
+            + self.e_a.size_in_bytes()
+            + self.a_a.size_in_bytes()
+            + self.v_a.size_in_bytes()
+            + self.eav_c.size_in_bytes()
+            + self.vea_c.size_in_bytes()
+            + self.ave_c.size_in_bytes()
+            + self.vae_c.size_in_bytes()
+            + self.eva_c.size_in_bytes()
+            + self.aev_c.size_in_bytes()
+    }
+}
+
+impl<U, B> Layout for SuccinctArchive<U, B>
+where
+    U: Layout,
+    B: Layout,
+{
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        if layout::take(bytes, ARCHIVE_MAGIC.len())? != ARCHIVE_MAGIC
+            || layout::read_usize(bytes)? as u64 != ARCHIVE_VERSION
+        {
+            return Err(BlobParseError::Deserialize);
+        }
+        U::skip(bytes)?;
+        for _ in 0..3 {
+            EliasFano::skip(bytes)?;
+        }
+        for _ in 0..6 {
+            WaveletMatrix::<B>::skip(bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl<U, B> SuccinctArchive<U, B>
+where
+    U: Universe + Serializable,
+    B: Serializable,
+{
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.size_in_bytes());
+        self.serialize_into(&mut buffer)
+            .expect("writing to a vec can't fail");
+        buffer
+    }
+}
+
+impl<U, B> SuccinctArchive<U, B>
 where
     U: Universe,
     B: Build + Access + Rank + Select + NumBits,
 {
-    fn into_blob(self) -> anybytes::Bytes {
-        todo!()
+    /// Checks that the indices and wavelet matrices fit together,
+    /// so that queries stay within the bounds of each structure.
+    ///
+    /// The rank and select directories within the bit vectors are not rebuilt,
+    /// they are only checked to fit into their serialized bytes.
+    fn check_consistency(&self) -> Result<(), BlobParseError> {
+        let tribles = self.eav_c.len();
+        let domain = self.domain.len();
+        let matrices = [
+            &self.eav_c,
+            &self.vea_c,
+            &self.ave_c,
+            &self.vae_c,
+            &self.eva_c,
+            &self.aev_c,
+        ];
+        if matrices
+            .iter()
+            .any(|m| m.len() != tribles || m.alph_size() > domain)
+        {
+            return Err(BlobParseError::Deserialize);
+        }
+        if [&self.e_a, &self.a_a, &self.v_a]
+            .iter()
+            .any(|a| a.len() != domain || a.universe() != tribles + 1)
+        {
+            return Err(BlobParseError::Deserialize);
+        }
+        Ok(())
+    }
+}
+
+/// Parsing copies the blob into the `sucds` structures, so unlike a
+/// [SimpleArchive](crate::triblearchive::SimpleArchive) this is not zero-copy
+/// and a memory mapped blob is read in full.
+///
+/// The blob is walked with [Layout] before it is deserialized,
+/// so corrupt lengths are rejected instead of being allocated.
+impl<U, B> Bloblike for SuccinctArchive<U, B>
+where
+    U: Universe + Serializable + Layout,
+    B: Build + Access + Rank + Select + NumBits + Serializable + Layout,
+{
+    fn into_blob(self) -> Bytes {
+        self.to_bytes().into()
     }
 
-    fn from_blob(_blob: anybytes::Bytes) -> Result<Self, crate::BlobParseError> {
-        todo!()
+    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
+        let mut layout = &blob[..];
+        Self::skip(&mut layout)?;
+        if !layout.is_empty() {
+            return Err(BlobParseError::TrailingBytes);
+        }
+        let archive =
+            Self::deserialize_from(&blob[..]).map_err(|_| BlobParseError::Deserialize)?;
+        archive.check_consistency()?;
+        Ok(archive)
     }
 
-    fn as_handle<H>(&self) -> crate::Handle<H, Self>
+    fn as_handle<H>(&self) -> Handle<H, Self>
     where
         H: Digest<OutputSize = U32>,
     {
-        todo!()
+        let digest = H::digest(self.to_bytes());
+        unsafe { Handle::new(Hash::new(digest.into())) }
     }
 }
 
@@ -276,7 +466,7 @@ where
 mod tests {
     use std::convert::TryInto;
 
-    use crate::{find, trible::Trible, types::ShortString, ufoid, Id, NS};
+    use crate::{and, find, trible::Trible, types::ShortString, ufoid, Id, NS, VALUE_LEN};
 
     use super::*;
     use itertools::Itertools;
@@ -320,6 +510,25 @@ mod tests {
             assert_eq!(set, set_);
         }
 
+        #[test]
+        fn blob_roundtrip(entries in prop::collection::vec(prop::collection::vec(0u8..255, 64), 1..1024)) {
+            let mut set = TribleSet::new();
+            for entry in entries {
+                let mut key = [0; 64];
+                key.iter_mut().set_from(entry.iter().cloned());
+                set.insert(&Trible{ data: key});
+            }
+
+            let archive: SuccinctArchive::<CompressedUniverse<DacsOpt>, Rank9Sel> = (&set).into();
+            let handle: crate::Handle<crate::types::hash::Blake3, _> = archive.as_handle();
+            let blob = archive.into_blob();
+            let archive_: SuccinctArchive::<CompressedUniverse<DacsOpt>, Rank9Sel> = Bloblike::from_blob(blob).unwrap();
+            let set_: TribleSet = (&archive_).into();
+
+            assert_eq!(set, set_);
+            assert_eq!(handle, archive_.as_handle());
+        }
+
         #[test]
         fn ordered_universe(values in prop::collection::vec(prop::collection::vec(0u8..255, 32), 1..10000)) {
             let mut values: Vec<Value> = values.into_iter().map(|v| v.try_into().unwrap()).collect();
@@ -352,6 +561,10 @@ mod tests {
                 let found = u.search(&values[i]);
                 assert_eq!(original, found);
             }
+            for i in 0..u.len() {
+                assert_eq!(u.search_range(&(values[i]..=values[i])), i..i + 1);
+                assert_eq!(u.search_range(&(values[0]..=values[i])), 0..i + 1);
+            }
         }
     }
 
@@ -393,4 +606,70 @@ mod tests {
         .collect();
         assert_eq!(vec![Ok((juliet, "Juliet".try_into().unwrap(),))], r);
     }
+
+    #[test]
+    fn archive_value_range() {
+        let mut kb = TribleSet::new();
+        for name in ["Alice", "Albert", "Bob", "Al"] {
+            kb.union(knights::entity!({
+                name: name.try_into().unwrap()
+            }));
+        }
+
+        let archive: SuccinctArchive<CompressedUniverse<DacsOpt>, Rank9Sel> = (&kb).into();
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e, name),
+            and!(
+                knights::pattern!(ctx, archive, [{e @ name: name}]),
+                archive.value_range(name, ShortString::prefix_range("Al").unwrap())
+            )
+        )
+        .map(|r| String::from(&r.unwrap().1))
+        .sorted()
+        .collect();
+        assert_eq!(vec!["Al", "Albert", "Alice"], r);
+    }
+
+    #[test]
+    fn blob_parse_errors() {
+        let mut kb = TribleSet::new();
+        kb.union(knights::entity!({
+            name: "Juliet".try_into().unwrap(),
+            loves: ufoid()
+        }));
+        let archive: SuccinctArchive<OrderedUniverse, Rank9Sel> = (&kb).into();
+        let blob = archive.into_blob().to_vec();
+        type Archive = SuccinctArchive<OrderedUniverse, Rank9Sel>;
+
+        let mut huge = blob.clone();
+        huge[16..24].copy_from_slice(&usize::MAX.to_le_bytes());
+        assert_eq!(
+            Archive::from_blob(huge.into()).err(),
+            Some(BlobParseError::Deserialize)
+        );
+
+        assert_eq!(
+            Archive::from_blob(blob[..blob.len() - 1].to_vec().into()).err(),
+            Some(BlobParseError::Deserialize)
+        );
+
+        let mut trailing = blob.clone();
+        trailing.push(0);
+        assert_eq!(
+            Archive::from_blob(trailing.into()).err(),
+            Some(BlobParseError::TrailingBytes)
+        );
+
+        // A smaller domain that still has a valid layout.
+        let mut shrunk = blob[..16].to_vec();
+        let domain = usize::from_le_bytes(blob[16..24].try_into().unwrap());
+        shrunk.extend_from_slice(&(domain - 1).to_le_bytes());
+        shrunk.extend_from_slice(&blob[24 + VALUE_LEN..]);
+        assert_eq!(
+            Archive::from_blob(shrunk.into()).err(),
+            Some(BlobParseError::Deserialize)
+        );
+    }
 }
@@ -0,0 +1,179 @@
+use std::convert::{TryFrom, TryInto};
+
+use sucds::bit_vectors::{BitVector, DArray, Rank9Sel};
+use sucds::char_sequences::WaveletMatrix;
+use sucds::int_vectors::{CompactVector, DacsOpt};
+use sucds::mii_sequences::EliasFano;
+
+use crate::{BlobParseError, VALUE_LEN};
+
+use super::{CompressedUniverse, OrderedUniverse};
+
+/// Walks the `sucds` serialization of a type without allocating anything.
+///
+/// `sucds` allocates its buffers with lengths read from the serialized bytes
+/// before it reads their content, so a corrupt length would abort the process.
+/// Walking a blob first checks that every length fits into the remaining bytes.
+pub trait Layout {
+    /// The serialized length, if it is the same for every value.
+    const LEN: Option<usize> = None;
+
+    /// Advances `bytes` past one serialized value.
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError>;
+}
+
+pub(super) fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], BlobParseError> {
+    if bytes.len() < len {
+        return Err(BlobParseError::Deserialize);
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// Reads a little endian `u64` length, rejecting ones that overflow `usize`.
+pub(super) fn read_usize(bytes: &mut &[u8]) -> Result<usize, BlobParseError> {
+    let le = take(bytes, 8)?;
+    usize::try_from(u64::from_le_bytes(le.try_into().unwrap()))
+        .map_err(|_| BlobParseError::Deserialize)
+}
+
+/// Skips `count` items of `len` bytes each.
+fn skip_items(bytes: &mut &[u8], count: usize, len: usize) -> Result<(), BlobParseError> {
+    let total = count.checked_mul(len).ok_or(BlobParseError::Deserialize)?;
+    take(bytes, total).map(|_| ())
+}
+
+fn skip_option(
+    bytes: &mut &[u8],
+    skip: fn(&mut &[u8]) -> Result<(), BlobParseError>,
+) -> Result<(), BlobParseError> {
+    if take(bytes, 1)?[0] != 0 {
+        skip(bytes)?;
+    }
+    Ok(())
+}
+
+macro_rules! primitive_layout {
+    ($int:ty) => {
+        impl Layout for $int {
+            const LEN: Option<usize> = Some(std::mem::size_of::<$int>());
+
+            fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+                take(bytes, std::mem::size_of::<$int>()).map(|_| ())
+            }
+        }
+    };
+}
+
+primitive_layout!(bool);
+primitive_layout!(u16);
+primitive_layout!(usize);
+primitive_layout!(isize);
+
+impl<T: Layout> Layout for Vec<T> {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        let count = read_usize(bytes)?;
+        match T::LEN {
+            Some(len) => skip_items(bytes, count, len),
+            // Every item takes at least one byte, so this ends with the bytes.
+            None => (0..count).try_for_each(|_| T::skip(bytes)),
+        }
+    }
+}
+
+impl<T: Layout> Layout for Option<T> {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        skip_option(bytes, T::skip)
+    }
+}
+
+impl Layout for BitVector {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        let words = read_usize(bytes)?;
+        skip_items(bytes, words, 8)?;
+        let len = read_usize(bytes)?;
+        match words.checked_mul(64) {
+            Some(bits) if len <= bits => Ok(()),
+            _ => Err(BlobParseError::Deserialize),
+        }
+    }
+}
+
+fn skip_rank9sel_index(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+    usize::skip(bytes)?;
+    Vec::<usize>::skip(bytes)?;
+    Option::<Vec<usize>>::skip(bytes)?;
+    Option::<Vec<usize>>::skip(bytes)
+}
+
+fn skip_darray_index(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+    Vec::<isize>::skip(bytes)?;
+    Vec::<u16>::skip(bytes)?;
+    Vec::<usize>::skip(bytes)?;
+    usize::skip(bytes)?;
+    bool::skip(bytes)
+}
+
+impl Layout for Rank9Sel {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        BitVector::skip(bytes)?;
+        skip_rank9sel_index(bytes)
+    }
+}
+
+impl Layout for DArray {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        BitVector::skip(bytes)?;
+        skip_darray_index(bytes)?;
+        skip_option(bytes, skip_darray_index)?;
+        skip_option(bytes, skip_rank9sel_index)
+    }
+}
+
+impl Layout for EliasFano {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        DArray::skip(bytes)?;
+        BitVector::skip(bytes)?;
+        usize::skip(bytes)?;
+        usize::skip(bytes)
+    }
+}
+
+impl<B: Layout> Layout for WaveletMatrix<B> {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        Vec::<B>::skip(bytes)?;
+        usize::skip(bytes)
+    }
+}
+
+impl Layout for CompactVector {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        BitVector::skip(bytes)?;
+        usize::skip(bytes)?;
+        if read_usize(bytes)? > 64 {
+            return Err(BlobParseError::Deserialize);
+        }
+        Ok(())
+    }
+}
+
+impl Layout for DacsOpt {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        Vec::<CompactVector>::skip(bytes)?;
+        Vec::<Rank9Sel>::skip(bytes)
+    }
+}
+
+impl Layout for OrderedUniverse {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        let count = read_usize(bytes)?;
+        skip_items(bytes, count, VALUE_LEN)
+    }
+}
+
+impl<C: Layout> Layout for CompressedUniverse<C> {
+    fn skip(bytes: &mut &[u8]) -> Result<(), BlobParseError> {
+        Vec::<C>::skip(bytes)
+    }
+}
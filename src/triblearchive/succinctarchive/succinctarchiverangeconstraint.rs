@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+use super::*;
+use crate::query::*;
+use crate::Valuelike;
+
+/// Proposes the values of a [SuccinctArchive] that lie within a [ValueRange],
+/// by searching the sorted domain for the positions covered by the range.
+pub struct SuccinctArchiveRangeConstraint<'a, V, R, U, B>
+where
+    V: Valuelike,
+    R: ValueRange,
+    U: Universe,
+    B: Build + Access + Rank + Select + NumBits,
+{
+    variable_v: Variable<V>,
+    range: R,
+    archive: &'a SuccinctArchive<U, B>,
+}
+
+impl<'a, V, R, U, B> SuccinctArchiveRangeConstraint<'a, V, R, U, B>
+where
+    V: Valuelike,
+    R: ValueRange,
+    U: Universe,
+    B: Build + Access + Rank + Select + NumBits,
+{
+    pub fn new(variable_v: Variable<V>, range: R, archive: &'a SuccinctArchive<U, B>) -> Self {
+        SuccinctArchiveRangeConstraint {
+            variable_v,
+            range,
+            archive,
+        }
+    }
+
+    fn domain_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.range
+            .bounds()
+            .into_iter()
+            .map(move |bounds| self.archive.domain.search_range(&bounds))
+    }
+
+    /// Checks if the domain value at `d` occurs in the value position of a trible.
+    fn is_value(&self, d: usize) -> bool {
+        let start = self.archive.v_a.select(d).unwrap();
+        let end = if d + 1 < self.archive.domain.len() {
+            self.archive.v_a.select(d + 1).unwrap()
+        } else {
+            self.archive.vea_c.len()
+        };
+        start < end
+    }
+}
+
+impl<'a, V, R, U, B> Constraint<'a> for SuccinctArchiveRangeConstraint<'a, V, R, U, B>
+where
+    V: Valuelike,
+    R: ValueRange,
+    U: Universe,
+    B: Build + Access + Rank + Select + NumBits,
+{
+    fn variables(&self) -> VariableSet {
+        VariableSet::new_singleton(self.variable_v.index)
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        self.variable_v.index == variable
+    }
+
+    fn estimate(&self, _variable: VariableId, _binding: &Binding) -> usize {
+        // The domain also contains entities and attributes,
+        // so this is only an upper bound on the number of values.
+        self.domain_ranges().map(|r| r.len()).sum()
+    }
+
+    fn propose(&self, _variable: VariableId, _binding: &Binding) -> Vec<Value> {
+        self.domain_ranges()
+            .flatten()
+            .filter(|&d| self.is_value(d))
+            .map(|d| self.archive.domain.access(d))
+            .filter(|v| self.range.contains(v))
+            .collect()
+    }
+
+    fn confirm(&self, _variable: VariableId, _binding: &Binding, proposals: &mut Vec<Value>) {
+        proposals.retain(|value| {
+            self.range.contains(value)
+                && self
+                    .archive
+                    .domain
+                    .search(value)
+                    .is_some_and(|d| self.is_value(d))
+        });
+    }
+}
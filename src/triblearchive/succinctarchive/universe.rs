@@ -2,6 +2,8 @@ use crate::Value;
 use crate::VALUE_LEN;
 
 use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::ops::{Range, RangeInclusive};
 
 use indxvec::Search;
 use sucds::int_vectors::{Access as IAccess, Build as IBuild, NumVals};
@@ -15,6 +17,29 @@ pub trait Universe {
     fn search(&self, v: &Value) -> Option<usize>;
     fn size_in_bytes(&self) -> usize;
     fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the positions of all values that lie within `range`.
+    fn search_range(&self, range: &RangeInclusive<Value>) -> Range<usize> {
+        let partition_point = |pred: &dyn Fn(&Value) -> bool| {
+            let (mut lo, mut hi) = (0, self.len());
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                if pred(&self.access(mid)) {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            lo
+        };
+        let start = partition_point(&|v| v < range.start());
+        let end = partition_point(&|v| v <= range.end());
+        start..std::cmp::max(start, end)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +72,38 @@ impl Universe for OrderedUniverse {
     fn len(&self) -> usize {
         self.values.len()
     }
+
+    fn search_range(&self, range: &RangeInclusive<Value>) -> Range<usize> {
+        let start = self.values.partition_point(|v| v < range.start());
+        let end = self.values.partition_point(|v| v <= range.end());
+        start..std::cmp::max(start, end)
+    }
+}
+
+impl Serializable for OrderedUniverse {
+    fn serialize_into<W: Write>(&self, mut writer: W) -> anyhow::Result<usize> {
+        let mut mem = self.values.len().serialize_into(&mut writer)?;
+        for value in &self.values {
+            writer.write_all(value)?;
+            mem += VALUE_LEN;
+        }
+        Ok(mem)
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> anyhow::Result<Self> {
+        let len = usize::deserialize_from(&mut reader)?;
+        let mut values = Vec::new();
+        for _ in 0..len {
+            let mut value: Value = [0; VALUE_LEN];
+            reader.read_exact(&mut value)?;
+            values.push(value);
+        }
+        Ok(Self { values })
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        usize::size_of().unwrap() + self.values.len() * VALUE_LEN
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -127,3 +184,27 @@ where
         self.segments[0].num_vals()
     }
 }
+
+impl<C> Serializable for CompressedUniverse<C>
+where
+    C: NumVals + Serializable,
+{
+    fn serialize_into<W: Write>(&self, writer: W) -> anyhow::Result<usize> {
+        self.segments.serialize_into(writer)
+    }
+
+    fn deserialize_from<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let segments = Vec::<C>::deserialize_from(reader)?;
+        if segments.len() != 4 {
+            anyhow::bail!("compressed universe must have exactly four segments");
+        }
+        if segments.iter().any(|c| c.num_vals() != segments[0].num_vals()) {
+            anyhow::bail!("compressed universe segments differ in length");
+        }
+        Ok(Self { segments })
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.segments.size_in_bytes()
+    }
+}
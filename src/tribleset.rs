@@ -1,8 +1,10 @@
 mod triblesetconstraint;
+mod triblesetrangeconstraint;
 
 use triblesetconstraint::*;
+use triblesetrangeconstraint::*;
 
-use crate::query::TriblePattern;
+use crate::query::{RangePattern, TriblePattern, ValueRange};
 
 use crate::patch::{Entry, PATCH};
 use crate::trible::{
@@ -12,6 +14,9 @@ use crate::trible::{
 use crate::{Id, Value, Valuelike};
 use std::iter::FromIterator;
 
+/// Sets with fewer tribles are bulk loaded without spawning tasks.
+const PARALLEL_LEN: usize = 4096;
+
 #[derive(Debug, Clone)]
 pub struct TribleSet {
     pub eav: PATCH<64, EAVOrder, TribleSegmentation>,
@@ -43,6 +48,50 @@ impl TribleSet {
         }
     }
 
+    /// Builds the set from tribles that are sorted in EAV order and free of duplicates,
+    /// like those of a validated [SimpleArchive](crate::triblearchive::SimpleArchive).
+    ///
+    /// The indices are built bottom up with [PATCH::from_sorted], and in parallel for large inputs.
+    pub(crate) fn from_sorted<'a, I>(tribles: I) -> TribleSet
+    where
+        I: IntoIterator<Item = &'a [u8; TRIBLE_LEN]>,
+    {
+        let entries: Vec<Entry<TRIBLE_LEN>> = tribles.into_iter().map(Entry::new).collect();
+        Self::from_sorted_entries(entries)
+    }
+
+    fn from_sorted_entries(entries: Vec<Entry<TRIBLE_LEN>>) -> TribleSet {
+        if entries.len() < PARALLEL_LEN {
+            return TribleSet {
+                eav: PATCH::from_sorted(&entries),
+                eva: PATCH::from_entries(entries.clone()),
+                aev: PATCH::from_entries(entries.clone()),
+                ave: PATCH::from_entries(entries.clone()),
+                vea: PATCH::from_entries(entries.clone()),
+                vae: PATCH::from_entries(entries),
+            };
+        }
+
+        let mut set = TribleSet::new();
+        let TribleSet {
+            eav,
+            eva,
+            aev,
+            ave,
+            vea,
+            vae,
+        } = &mut set;
+        rayon::scope(|s| {
+            s.spawn(|_| *eva = PATCH::from_entries(entries.clone()));
+            s.spawn(|_| *aev = PATCH::from_entries(entries.clone()));
+            s.spawn(|_| *ave = PATCH::from_entries(entries.clone()));
+            s.spawn(|_| *vea = PATCH::from_entries(entries.clone()));
+            s.spawn(|_| *vae = PATCH::from_entries(entries.clone()));
+            *eav = PATCH::from_sorted(&entries);
+        });
+        set
+    }
+
     pub fn len(&self) -> usize {
         return self.eav.len() as usize;
     }
@@ -60,6 +109,52 @@ impl TribleSet {
         self.vea.insert(&key);
         self.vae.insert(&key);
     }
+
+    pub fn remove(&mut self, trible: &Trible) {
+        self.remove_raw(&trible.data)
+    }
+
+    pub fn remove_raw(&mut self, data: &[u8; TRIBLE_LEN]) {
+        self.eav.remove(data);
+        self.eva.remove(data);
+        self.aev.remove(data);
+        self.ave.remove(data);
+        self.vea.remove(data);
+        self.vae.remove(data);
+    }
+
+    pub fn difference(&self, other: &Self) -> Self {
+        TribleSet {
+            eav: self.eav.difference(&other.eav),
+            eva: self.eva.difference(&other.eva),
+            aev: self.aev.difference(&other.aev),
+            ave: self.ave.difference(&other.ave),
+            vea: self.vea.difference(&other.vea),
+            vae: self.vae.difference(&other.vae),
+        }
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        TribleSet {
+            eav: self.eav.intersection(&other.eav),
+            eva: self.eva.intersection(&other.eva),
+            aev: self.aev.intersection(&other.aev),
+            ave: self.ave.intersection(&other.ave),
+            vea: self.vea.intersection(&other.vea),
+            vae: self.vae.intersection(&other.vae),
+        }
+    }
+
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        TribleSet {
+            eav: self.eav.symmetric_difference(&other.eav),
+            eva: self.eva.symmetric_difference(&other.eva),
+            aev: self.aev.symmetric_difference(&other.aev),
+            ave: self.ave.symmetric_difference(&other.ave),
+            vea: self.vea.symmetric_difference(&other.vea),
+            vae: self.vae.symmetric_difference(&other.vae),
+        }
+    }
 }
 
 impl PartialEq for TribleSet {
@@ -72,13 +167,12 @@ impl Eq for TribleSet {}
 
 impl FromIterator<Trible> for TribleSet {
     fn from_iter<I: IntoIterator<Item = Trible>>(iter: I) -> Self {
-        let mut set = TribleSet::new();
-
-        for t in iter {
-            set.insert(&t);
-        }
-
-        set
+        let mut entries: Vec<Entry<TRIBLE_LEN>> =
+            iter.into_iter().map(|t| Entry::new(&t.data)).collect();
+        // The tree order of EAV is the order of the tribles themselves.
+        entries.sort_unstable_by(|a, b| a.key().cmp(b.key()));
+        entries.dedup_by(|a, b| a.key() == b.key());
+        Self::from_sorted_entries(entries)
     }
 }
 
@@ -100,11 +194,31 @@ impl TriblePattern for TribleSet {
     }
 }
 
+impl RangePattern for TribleSet {
+    type RangeConstraint<'a, V, R>
+     = TribleSetRangeConstraint<'a, V, R>
+     where V: Valuelike,
+           R: ValueRange + 'a;
+
+    fn value_range<'a, V, R>(
+        &'a self,
+        v: crate::query::Variable<V>,
+        range: R,
+    ) -> Self::RangeConstraint<'a, V, R>
+    where
+        V: Valuelike,
+        R: ValueRange + 'a,
+    {
+        TribleSetRangeConstraint::new(v, range, self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::convert::TryInto;
 
-    use crate::{types::ShortString, ufoid, Id, NS};
+    use crate::query::{and, find};
+    use crate::{id_into_value, types::ShortString, ufoid, Id, NS};
 
     use super::*;
     use fake::{faker::name::raw::Name, locales::EN, Fake};
@@ -165,6 +279,76 @@ mod tests {
         assert_eq!(kb.len(), 4000000);
     }
 
+    #[test]
+    fn difference() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+        kb.union(knights::entity!(juliet, {
+            name: "Juliet".try_into().unwrap(),
+            loves: romeo
+        }));
+        let before = kb.clone();
+        kb.union(knights::entity!(romeo, {
+            name: "Romeo".try_into().unwrap(),
+            loves: juliet
+        }));
+
+        let changes = kb.difference(&before);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(kb.intersection(&before), before);
+        assert_eq!(kb.symmetric_difference(&before), changes);
+
+        let mut retracted = kb.clone();
+        retracted.remove(&Trible::new(romeo, knights::ids::loves, juliet));
+        assert_eq!(retracted.len(), 3);
+        assert_eq!(retracted.eav.len(), retracted.vae.len());
+        assert_eq!(kb.difference(&retracted).len(), 1);
+    }
+
+    #[test]
+    fn infixes_in_tree_order() {
+        let juliet = ufoid();
+        let romeo = ufoid();
+
+        let mut kb = TribleSet::new();
+        kb.union(knights::entity!(juliet, { loves: romeo }));
+        kb.union(knights::entity!(romeo, { loves: juliet }));
+
+        // The values follow the attribute in AVE order,
+        // but not in the key, which is in EAV order.
+        let mut lovers = vec![];
+        kb.ave.infixes(&knights::ids::loves, |v: [u8; 32]| lovers.push(v));
+        lovers.sort();
+        let mut expected = vec![id_into_value(juliet), id_into_value(romeo)];
+        expected.sort();
+        assert_eq!(lovers, expected);
+    }
+
+    #[test]
+    fn value_range() {
+        let mut kb = TribleSet::new();
+        for name in ["Alice", "Albert", "Bob", "Al"] {
+            kb.union(knights::entity!({
+                name: name.try_into().unwrap()
+            }));
+        }
+
+        let r: Vec<_> = find!(
+            ctx,
+            (e, name),
+            and!(
+                knights::pattern!(ctx, kb, [{e @ name: name}]),
+                kb.value_range(name, ShortString::prefix_range("Al").unwrap())
+            )
+        )
+        .map(|r| String::from(&r.unwrap().1))
+        .sorted()
+        .collect();
+        assert_eq!(vec!["Al", "Albert", "Alice"], r);
+    }
+
     proptest! {
         #[test]
         fn insert(entries in prop::collection::vec(prop::collection::vec(0u8..255, 64), 1..1024)) {
@@ -175,5 +359,42 @@ mod tests {
                 set.insert(&Trible{ data: key});
             }
         }
+
+        #[test]
+        fn from_iter(entries in prop::collection::vec(prop::collection::vec(1u8..4, 64), 1..1024)) {
+            let tribles: Vec<Trible> = entries
+                .into_iter()
+                .map(|entry| Trible { data: entry.try_into().unwrap() })
+                .collect();
+            assert_bulk_loaded(&tribles);
+        }
+    }
+
+    fn assert_bulk_loaded(tribles: &[Trible]) {
+        let mut inserted = TribleSet::new();
+        for trible in tribles {
+            inserted.insert(trible);
+        }
+        let bulk: TribleSet = tribles.iter().copied().collect();
+        assert_eq!(bulk.len(), inserted.len());
+        assert!(bulk.eav == inserted.eav);
+        assert!(bulk.eva == inserted.eva);
+        assert!(bulk.aev == inserted.aev);
+        assert!(bulk.ave == inserted.ave);
+        assert!(bulk.vea == inserted.vea);
+        assert!(bulk.vae == inserted.vae);
+        assert_eq!(
+            bulk.ave.segmented_len(&[0; 0]),
+            inserted.ave.segmented_len(&[0; 0])
+        );
+    }
+
+    #[test]
+    fn from_iter_parallel() {
+        let ids: Vec<Id> = (0..16).map(|_| ufoid()).collect();
+        let tribles: Vec<Trible> = (0..PARALLEL_LEN * 2)
+            .map(|i| Trible::new(ufoid(), ids[i % 4], ids[i % 16]))
+            .collect();
+        assert_bulk_loaded(&tribles);
     }
 }
@@ -297,4 +297,9 @@ where
             _ => panic!("invalid trible constraint state"),
         }
     }
+
+    fn count(&self, variable: VariableId, binding: &Binding) -> Option<usize> {
+        // The estimates are the exact number of distinct segments.
+        Some(self.estimate(variable, binding))
+    }
 }
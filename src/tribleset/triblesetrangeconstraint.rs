@@ -0,0 +1,73 @@
+use super::*;
+use crate::query::*;
+
+/// Proposes the values of a [TribleSet] that lie within a [ValueRange],
+/// by walking only the matching parts of the value-first `vea` index.
+pub struct TribleSetRangeConstraint<'a, V, R>
+where
+    V: Valuelike,
+    R: ValueRange,
+{
+    variable_v: Variable<V>,
+    range: R,
+    set: &'a TribleSet,
+}
+
+impl<'a, V, R> TribleSetRangeConstraint<'a, V, R>
+where
+    V: Valuelike,
+    R: ValueRange,
+{
+    pub fn new(variable_v: Variable<V>, range: R, set: &'a TribleSet) -> Self {
+        TribleSetRangeConstraint {
+            variable_v,
+            range,
+            set,
+        }
+    }
+}
+
+impl<'a, V, R> Constraint<'a> for TribleSetRangeConstraint<'a, V, R>
+where
+    V: Valuelike,
+    R: ValueRange,
+{
+    fn variables(&self) -> VariableSet {
+        VariableSet::new_singleton(self.variable_v.index)
+    }
+
+    fn variable(&self, variable: VariableId) -> bool {
+        self.variable_v.index == variable
+    }
+
+    fn estimate(&self, _variable: VariableId, _binding: &Binding) -> usize {
+        self.range
+            .bounds()
+            .iter()
+            .map(|bounds| {
+                self.set
+                    .vea
+                    .segmented_len_range(&[0; 0], bounds.start(), bounds.end())
+                    as usize
+            })
+            .sum()
+    }
+
+    fn propose(&self, _variable: VariableId, _binding: &Binding) -> Vec<Value> {
+        let mut r = vec![];
+        for bounds in self.range.bounds() {
+            self.set
+                .vea
+                .infixes_range(&[0; 0], bounds.start(), bounds.end(), |v: Value| {
+                    if self.range.contains(&v) {
+                        r.push(v)
+                    }
+                });
+        }
+        r
+    }
+
+    fn confirm(&self, _variable: VariableId, _binding: &Binding, proposals: &mut Vec<Value>) {
+        proposals.retain(|value| self.range.contains(value) && self.set.vea.has_prefix(value));
+    }
+}
@@ -1,6 +1,7 @@
 //! This is a collection of Rust types that can be (de)serialized as
 //! [Value]s, and [Blob]s.
 
+pub mod chunked;
 pub mod ed25519;
 pub mod f256;
 pub mod hash;
@@ -8,6 +9,7 @@ pub mod shortstring;
 pub mod time;
 pub mod zcstring;
 
+pub use chunked::ChunkTree;
 pub use hash::Hash;
 pub use shortstring::*;
 pub use time::*;
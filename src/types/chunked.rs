@@ -0,0 +1,388 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::ops::Range;
+
+use anybytes::Bytes;
+use digest::{typenum::U32, Digest};
+use futures::{stream, Stream};
+
+use crate::remote::repo::{Pull, Push};
+use crate::{types::Hash, BlobParseError, BlobSet, Bloblike, Handle, Value};
+
+/// Chunks are never cut before this many bytes.
+pub const MIN_CHUNK_LEN: usize = 16 * 1024;
+/// Chunks are always cut after this many bytes.
+pub const MAX_CHUNK_LEN: usize = 256 * 1024;
+/// The average chunk length is about `MIN_CHUNK_LEN + 2^AVG_CHUNK_BITS`.
+const AVG_CHUNK_BITS: u32 = 16;
+/// The average number of entries in a [ChunkTree] node is about `2^AVG_FANOUT_BITS`.
+const AVG_FANOUT_BITS: u32 = 8;
+/// The maximal number of entries in a [ChunkTree] node.
+pub const FANOUT: usize = 1024;
+/// The maximal height of a [ChunkTree], which suffices for any content,
+/// because every level has at most half as many nodes as the one below.
+pub const MAX_HEIGHT: u8 = 64;
+
+const ENTRY_LEN: usize = 40;
+
+/// Random values for the gear hash, generated with splitmix64.
+static GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9E3779B97F4A7C15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits `data` into content defined chunks.
+///
+/// The boundaries are found with a rolling gear hash, so they only
+/// depend on the bytes right before them and an edit only changes
+/// the chunks around it.
+pub fn split(data: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start == data.len() {
+            return None;
+        }
+        let end = next_boundary(&data[start..]) + start;
+        let chunk = start..end;
+        start = end;
+        Some(chunk)
+    })
+}
+
+fn next_boundary(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_LEN {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_LEN);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_LEN) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash >> (64 - AVG_CHUNK_BITS) == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// A node in the tree of handles that describes a chunked blob.
+///
+/// The first byte is the height of the node, followed by entries of
+/// a hash and the little endian length of the content it covers.
+/// The entries of nodes with height zero are chunks,
+/// the entries of higher nodes are nodes one level below.
+pub struct ChunkTree(Bytes);
+
+impl ChunkTree {
+    fn new(height: u8, entries: &[(Value, u64)]) -> Self {
+        let mut node = Vec::with_capacity(1 + entries.len() * ENTRY_LEN);
+        node.push(height);
+        for (hash, len) in entries {
+            node.extend_from_slice(hash);
+            node.extend_from_slice(&len.to_le_bytes());
+        }
+        ChunkTree(node.into())
+    }
+
+    pub fn height(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Returns the hashes of the children and the length of their content.
+    pub fn entries(&self) -> impl Iterator<Item = (Value, u64)> + '_ {
+        self.0[1..].chunks_exact(ENTRY_LEN).map(parse_entry)
+    }
+
+    /// The length of the content covered by this node.
+    pub fn len(&self) -> u64 {
+        self.entries().map(|(_, len)| len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn entry(&self, index: usize) -> Option<(Value, u64)> {
+        let start = 1 + index * ENTRY_LEN;
+        self.0.get(start..start + ENTRY_LEN).map(parse_entry)
+    }
+}
+
+fn parse_entry(entry: &[u8]) -> (Value, u64) {
+    (
+        entry[..32].try_into().unwrap(),
+        u64::from_le_bytes(entry[32..].try_into().unwrap()),
+    )
+}
+
+impl Bloblike for ChunkTree {
+    fn into_blob(self) -> Bytes {
+        self.0
+    }
+
+    fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
+        if blob.is_empty() || (blob.len() - 1) % ENTRY_LEN != 0 {
+            return Err(BlobParseError::BadLength(blob.len()));
+        }
+        if blob[0] > MAX_HEIGHT {
+            return Err(BlobParseError::Malformed(Cow::Borrowed(
+                "chunk tree exceeds the maximal height",
+            )));
+        }
+        Ok(ChunkTree(blob))
+    }
+
+    fn as_handle<H>(&self) -> Handle<H, Self>
+    where
+        H: Digest<OutputSize = U32>,
+    {
+        let digest = H::digest(&self.0);
+        unsafe { Handle::new(Hash::new(digest.into())) }
+    }
+}
+
+/// Splits `content` into chunks and returns the root of their [ChunkTree],
+/// together with the chunks and nodes that have to be stored.
+///
+/// The chunks share the memory of `content`.
+pub fn chunk<H>(content: Bytes) -> (Handle<H, ChunkTree>, BlobSet<H>)
+where
+    H: Digest<OutputSize = U32>,
+{
+    chunk_with_fanout(content, AVG_FANOUT_BITS, FANOUT)
+}
+
+/// Like the chunks, the nodes of every level are cut where an entry's hash
+/// has its lowest `avg_bits` bits unset, so an edit only changes the nodes
+/// on the path to it. Nodes have at least two entries, except for the last
+/// one of a level, and at most `max_fanout`.
+fn chunk_with_fanout<H>(
+    content: Bytes,
+    avg_bits: u32,
+    max_fanout: usize,
+) -> (Handle<H, ChunkTree>, BlobSet<H>)
+where
+    H: Digest<OutputSize = U32>,
+{
+    let mut blobs = BlobSet::new();
+    let mut entries: Vec<(Value, u64)> = split(&content)
+        .map(|range| {
+            let len = range.len() as u64;
+            (blobs.put_raw(content.slice(range)).bytes, len)
+        })
+        .collect();
+    let mut height = 0;
+    loop {
+        let nodes = group(&entries, avg_bits, max_fanout);
+        if nodes.len() <= 1 {
+            let root = blobs.put(ChunkTree::new(height, &entries));
+            return (root, blobs);
+        }
+        entries = nodes
+            .into_iter()
+            .map(|children| {
+                let node = ChunkTree::new(height, children);
+                let len = node.len();
+                (blobs.put(node).hash.bytes, len)
+            })
+            .collect();
+        height += 1;
+    }
+}
+
+/// Splits the entries of a level into the children of the nodes above.
+fn group(entries: &[(Value, u64)], avg_bits: u32, max_fanout: usize) -> Vec<&[(Value, u64)]> {
+    let mask = (1u32 << avg_bits) - 1;
+    let mut nodes = Vec::new();
+    let mut start = 0;
+    for (i, (hash, _)) in entries.iter().enumerate() {
+        let len = i + 1 - start;
+        let boundary = u32::from_le_bytes(hash[..4].try_into().unwrap()) & mask == 0;
+        if (boundary && len >= 2) || len == max_fanout {
+            nodes.push(&entries[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < entries.len() {
+        nodes.push(&entries[start..]);
+    }
+    nodes
+}
+
+/// Chunks `content` and pushes the chunks and their [ChunkTree] to `repo`.
+pub async fn push<H, R>(repo: &R, content: Bytes) -> Result<Handle<H, ChunkTree>, R::Err>
+where
+    H: Digest<OutputSize = U32>,
+    R: Push<H>,
+{
+    let (root, blobs) = chunk(content);
+    for (_, blob) in blobs {
+        repo.push(blob).await?;
+    }
+    Ok(root)
+}
+
+#[derive(Debug)]
+pub enum ReadErr<E> {
+    Pull(E),
+    Parse(BlobParseError),
+}
+
+/// Streams the content of a chunked blob in order,
+/// pulling nodes and chunks only once they are reached.
+pub fn read<'a, H, R>(
+    repo: &'a R,
+    root: Handle<H, ChunkTree>,
+) -> impl Stream<Item = Result<Bytes, ReadErr<R::Err>>> + 'a
+where
+    H: 'a,
+    R: Pull<H>,
+{
+    let stack: Vec<(ChunkTree, usize)> = Vec::new();
+    stream::unfold(
+        (Some(root.hash), stack),
+        move |(mut pending, mut stack)| async move {
+            loop {
+                if let Some(hash) = pending.take() {
+                    let parent = stack.last().map(|(parent, _)| parent.height());
+                    let node = repo
+                        .pull(hash)
+                        .await
+                        .map_err(ReadErr::Pull)
+                        .and_then(|blob| ChunkTree::from_blob(blob).map_err(ReadErr::Parse))
+                        .and_then(|node| match parent {
+                            // Children sit one level below their parent,
+                            // which also rules out cycles.
+                            Some(height) if node.height() + 1 != height => {
+                                Err(ReadErr::Parse(BlobParseError::Malformed(Cow::Borrowed(
+                                    "chunk tree node at the wrong height",
+                                ))))
+                            }
+                            _ => Ok(node),
+                        });
+                    match node {
+                        Ok(node) => stack.push((node, 0)),
+                        Err(e) => return Some((Err(e), (None, Vec::new()))),
+                    }
+                    continue;
+                }
+                let (node, index) = stack.last_mut()?;
+                let Some((hash, len)) = node.entry(*index) else {
+                    stack.pop();
+                    continue;
+                };
+                *index += 1;
+                if node.height() > 0 {
+                    pending = Some(Hash::new(hash));
+                    continue;
+                }
+                let chunk = match repo.pull(Hash::new(hash)).await {
+                    Ok(chunk) if chunk.len() as u64 == len => Ok(chunk),
+                    Ok(chunk) => Err(ReadErr::Parse(BlobParseError::BadLength(chunk.len()))),
+                    Err(e) => Err(ReadErr::Pull(e)),
+                };
+                if chunk.is_err() {
+                    stack.clear();
+                }
+                return Some((chunk, (None, stack)));
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::hash::Blake3;
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+    use rand::{rngs::StdRng, thread_rng, RngCore, SeedableRng};
+
+    fn read_all(blobs: &BlobSet<Blake3>, root: Handle<Blake3, ChunkTree>) -> Vec<u8> {
+        block_on(read(blobs, root).try_collect::<Vec<_>>())
+            .unwrap()
+            .iter()
+            .flat_map(|chunk| chunk.iter().copied())
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut content = vec![0; 2 * 1024 * 1024];
+        thread_rng().fill_bytes(&mut content);
+
+        for (avg_bits, max_fanout) in [(AVG_FANOUT_BITS, FANOUT), (1, 3)] {
+            let (root, blobs) =
+                chunk_with_fanout::<Blake3>(content.clone().into(), avg_bits, max_fanout);
+            let tree = ChunkTree::from_blob(blobs.get_raw(root.hash).unwrap().clone()).unwrap();
+            assert_eq!(tree.len(), content.len() as u64);
+            assert_eq!(read_all(&blobs, root), content);
+        }
+
+        let (root, blobs) = chunk::<Blake3>(Bytes::new());
+        assert!(read_all(&blobs, root).is_empty());
+    }
+
+    #[test]
+    fn malformed_heights() {
+        let too_high = ChunkTree::new(MAX_HEIGHT + 1, &[]).into_blob();
+        assert!(ChunkTree::from_blob(too_high).is_err());
+
+        // A node that claims to be above a chunk would treat it as a node.
+        let mut blobs: BlobSet<Blake3> = BlobSet::new();
+        let leaf = blobs.put(ChunkTree::new(0, &[]));
+        let root = blobs.put(ChunkTree::new(2, &[(leaf.hash.bytes, 0)]));
+        let result = block_on(read(&blobs, root).try_collect::<Vec<_>>());
+        assert!(matches!(result, Err(ReadErr::Parse(_))));
+    }
+
+    #[test]
+    fn edits_share_chunks() {
+        let mut content = vec![0; 2 * 1024 * 1024];
+        StdRng::seed_from_u64(2).fill_bytes(&mut content);
+        let (_, original) = chunk::<Blake3>(content.clone().into());
+
+        content.insert(1024 * 1024, 42);
+        let (_, edited) = chunk::<Blake3>(content.into());
+
+        let new = edited
+            .iter_raw()
+            .filter(|(hash, _)| original.get_raw(**hash).is_none())
+            .count();
+        // The chunk with the edit, the one after it if the edit removed
+        // a boundary, the node above them, which splits in two if one of
+        // the new chunks cuts a node boundary, and the root. The chunk
+        // boundaries depend on the content, which is seeded to keep it stable.
+        assert!(new <= 5, "{} of {} blobs changed", new, edited.len());
+    }
+
+    #[test]
+    fn edits_share_nodes() {
+        // Seeded, because the number of nodes on the path varies with the content.
+        let mut content = vec![0; 4 * 1024 * 1024];
+        StdRng::seed_from_u64(4).fill_bytes(&mut content);
+        let (_, original) = chunk_with_fanout::<Blake3>(content.clone().into(), 1, 4);
+
+        content.insert(2 * 1024 * 1024, 42);
+        let (_, edited) = chunk_with_fanout::<Blake3>(content.into(), 1, 4);
+
+        let new = edited
+            .iter_raw()
+            .filter(|(hash, _)| original.get_raw(**hash).is_none())
+            .count();
+        // Only the chunks and the nodes on the path to the edit change,
+        // instead of every node after it.
+        assert!(new <= 2 * 8, "{} of {} blobs changed", new, edited.len());
+    }
+}
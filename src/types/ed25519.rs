@@ -43,7 +43,7 @@ impl Valuelike for SComponent {
 impl Valuelike for VerifyingKey {
     fn from_value(value: crate::Value) -> Result<Self, ValueParseError> {
         VerifyingKey::from_bytes(&value)
-            .map_err(|_| ValueParseError::new(value, "failed to construct valid VerifyingKey"))
+            .map_err(|_| ValueParseError::BadVerifyingKey(value))
     }
 
     fn into_value(value: &Self) -> crate::Value {
@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::ops::RangeInclusive;
 
 use crate::{Value, ValueParseError, Valuelike};
 
@@ -8,7 +9,7 @@ pub enum FromStrError {
     InteriorNul,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 #[repr(transparent)]
 pub struct ShortString(Value);
 
@@ -28,12 +29,21 @@ impl ShortString {
 
         Ok(ShortString(data))
     }
+
+    /// Returns the range of all short strings starting with `prefix`,
+    /// for use with [crate::query::RangePattern].
+    pub fn prefix_range<S: AsRef<str>>(prefix: S) -> Result<RangeInclusive<Value>, FromStrError> {
+        let ShortString(start) = ShortString::new(prefix.as_ref())?;
+        let mut end = [u8::MAX; 32];
+        end[..prefix.as_ref().len()].copy_from_slice(prefix.as_ref().as_bytes());
+        Ok(start..=end)
+    }
 }
 
 impl Valuelike for ShortString {
     fn from_value(bytes: Value) -> Result<Self, ValueParseError> {
         std::str::from_utf8(&bytes[..])
-            .map_err(|_| ValueParseError::new(bytes, "failed to convert to utf-8 string"))?;
+            .map_err(|_| ValueParseError::BadUtf8(bytes))?;
         Ok(ShortString(bytes))
     }
 
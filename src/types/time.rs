@@ -1,11 +1,50 @@
 use std::convert::TryInto;
 
-use crate::Valuelike;
+use std::ops::RangeInclusive;
+
+use crate::query::ValueRange;
+use crate::{Value, Valuelike};
 
 use hifitime::prelude::*;
 
 pub struct NsTAIInterval(pub i128, pub i128);
 
+/// A [ValueRange] of all [NsTAIInterval]s that overlap the
+/// inclusive interval from the first to the second bound.
+///
+/// Intervals are ordered by their start, so the [ValueRange::bounds] can
+/// only exclude the intervals that start after the second bound. Those that
+/// end before the first bound are filtered one by one with [ValueRange::contains],
+/// which means that a query visits every interval starting before the second bound,
+/// not just the overlapping ones.
+pub struct NsTAIOverlap(pub i128, pub i128);
+
+impl ValueRange for NsTAIOverlap {
+    fn bounds(&self) -> Vec<RangeInclusive<Value>> {
+        // An overlapping interval has to start before our end.
+        // Negative starts sort after the positive ones in their
+        // big endian encoding, so they form a separate range.
+        let bound = |lower: i128, upper: i128| {
+            let mut start = [0; 32];
+            start[0..16].copy_from_slice(&lower.to_be_bytes());
+            let mut end = [u8::MAX; 32];
+            end[0..16].copy_from_slice(&upper.to_be_bytes());
+            start..=end
+        };
+        if self.1 < 0 {
+            vec![bound(i128::MIN, self.1)]
+        } else {
+            vec![bound(0, self.1), bound(i128::MIN, -1)]
+        }
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        let lower = i128::from_be_bytes(value[0..16].try_into().unwrap());
+        let upper = i128::from_be_bytes(value[16..32].try_into().unwrap());
+        lower <= self.1 && self.0 <= upper
+    }
+}
+
 impl Valuelike for NsTAIInterval {
     fn from_value(bytes: crate::Value) -> Result<Self, crate::ValueParseError> {
         let lower = i128::from_be_bytes(bytes[0..16].try_into().unwrap());
@@ -50,6 +89,25 @@ mod tests {
         let _ = NsTAIInterval::from_value(value);
     }
 
+    #[test]
+    fn overlap_range() {
+        let overlap = NsTAIOverlap(-10, 10);
+        for (interval, overlaps) in [
+            (NsTAIInterval(-20, -11), false),
+            (NsTAIInterval(-20, -10), true),
+            (NsTAIInterval(-5, 5), true),
+            (NsTAIInterval(10, 20), true),
+            (NsTAIInterval(11, 20), false),
+        ] {
+            let value = NsTAIInterval::into_value(&interval);
+            assert_eq!(overlap.contains(&value), overlaps);
+            // Only the start is bounded, so intervals that end too early
+            // are still within the bounds.
+            let bounded = interval.0 <= overlap.1;
+            assert_eq!(overlap.bounds().iter().any(|b| b.contains(&value)), bounded);
+        }
+    }
+
     #[test]
     fn hifitime_conversion() {
         let epoch: NsTAIInterval = NsTAIInterval(0, 0);
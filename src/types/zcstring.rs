@@ -27,8 +27,7 @@ impl Bloblike for ZCString {
     }
 
     fn from_blob(blob: Bytes) -> Result<Self, BlobParseError> {
-        std::str::from_utf8(&blob[..])
-            .map_err(|_| BlobParseError::new("failed to convert to utf-8 string"))?;
+        std::str::from_utf8(&blob[..]).map_err(|_| BlobParseError::BadUtf8)?;
         Ok(ZCString(blob))
     }
 
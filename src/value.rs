@@ -1,4 +1,6 @@
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{self, Debug};
 
 pub const VALUE_LEN: usize = 32;
 pub type Value = [u8; VALUE_LEN];
@@ -19,31 +21,61 @@ impl Valuelike for Value {
     }
 }
 
-pub struct ValueParseError {
-    value: Value,
-    msg: String,
+/// Why a [Value] couldn't be converted into a [Valuelike] type.
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValueParseError {
+    /// The value isn't valid UTF-8.
+    BadUtf8(Value),
+    /// The value isn't a valid ed25519 verifying key.
+    BadVerifyingKey(Value),
+    /// The value is malformed in some other way,
+    /// e.g. for types defined outside of this crate.
+    Malformed(Value, Cow<'static, str>),
 }
 
 impl ValueParseError {
+    #[deprecated(note = "use ValueParseError::Malformed or a more specific variant")]
     pub fn new(value: Value, msg: &str) -> Self {
-        ValueParseError {
-            value,
-            msg: msg.to_owned(),
+        Self::Malformed(value, Cow::Owned(msg.to_owned()))
+    }
+
+    /// The value that failed to parse.
+    pub fn value(&self) -> Value {
+        match self {
+            Self::BadUtf8(value) | Self::BadVerifyingKey(value) | Self::Malformed(value, _) => {
+                *value
+            }
         }
     }
 }
 
-impl Eq for ValueParseError {}
-impl PartialEq for ValueParseError {
-    fn eq(&self, other: &Self) -> bool {
-        self.value == other.value && self.msg == other.msg
+impl Debug for ValueParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadUtf8(value) => f.debug_tuple("BadUtf8").field(&hex::encode(value)).finish(),
+            Self::BadVerifyingKey(value) => f
+                .debug_tuple("BadVerifyingKey")
+                .field(&hex::encode(value))
+                .finish(),
+            Self::Malformed(value, msg) => f
+                .debug_tuple("Malformed")
+                .field(&hex::encode(value))
+                .field(msg)
+                .finish(),
+        }
     }
 }
-impl Debug for ValueParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ValueParseError")
-            .field("value", &hex::encode(&self.value))
-            .field("msg", &self.msg)
-            .finish()
+
+impl fmt::Display for ValueParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadUtf8(_) => write!(f, "value is not valid utf-8")?,
+            Self::BadVerifyingKey(_) => write!(f, "value is not a valid verifying key")?,
+            Self::Malformed(_, msg) => write!(f, "malformed value: {}", msg)?,
+        }
+        write!(f, " ({})", hex::encode(self.value()))
     }
 }
+
+impl Error for ValueParseError {}